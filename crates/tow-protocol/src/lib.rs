@@ -0,0 +1,385 @@
+//! the session protocol without any io, shared by the tunnel and the browser client
+//!
+//! the handshake response of the server tells in `x-tow-session` if it already had the session, `new` or `resumed`,
+//! browsers can not read it, so once a websocket is attached the server also sends it first as text
+//!
+//! both sides of a session keep the bytes read from their stream until the other side confirms them,
+//! when a websocket is attached each side sends its `write_cursor` as text, the other side drops the
+//! confirmed bytes from its buffer and sends the rest as binary messages
+//!
+//! when its stream ends a side sends `HALF_CLOSE` after all its bytes, the other side closes the writing side of
+//! its stream and keeps reading from it, the session is closed once both streams ended
+//!
+//! sides from before half closes ignore `HALF_CLOSE`, so each side sends `HALF_CLOSE_SUPPORTED` before its cursor,
+//! when the other side did not send it the session is closed once the stream of this side ends, as before
+
+/// the largest binary message sent over the websocket
+pub const MAX_BYTES_WS_MESSAGE: usize = 1024 * 8;
+
+/// the text message sent once the stream of a side ended and all its bytes were sent
+pub const HALF_CLOSE: &str = "fin";
+
+/// the text message sent before the cursor on each websocket by the sides that understand `HALF_CLOSE`
+pub const HALF_CLOSE_SUPPORTED: &str = "half-close";
+
+/// the close codes the server ends a session with, in the range reserved for applications
+pub mod close_code {
+    /// the tcp service refused the connection
+    pub const BACKEND_REFUSED: u16 = 4001;
+    /// the connection to the tcp service did not complete in time
+    pub const BACKEND_TIMEOUT: u16 = 4002;
+    /// the tcp service could not be resolved or reached
+    pub const BACKEND_UNREACHABLE: u16 = 4003;
+    /// the session was killed through the admin api
+    pub const KILLED: u16 = 4004;
+    /// no bytes went through the session for longer than the idle timeout
+    pub const IDLE_TIMEOUT: u16 = 4005;
+    /// the session was open for longer than the maximum lifetime
+    pub const MAX_LIFETIME: u16 = 4006;
+    /// the destination asked by the client is not allowed by the target policy
+    pub const TARGET_DENIED: u16 = 4007;
+    /// the server no longer had the session the client was resuming
+    pub const SESSION_LOST: u16 = 4008;
+}
+
+/// the first text message of the server on each websocket
+pub fn session_message(resumed: bool) -> &'static str {
+    if resumed {
+        "resumed"
+    } else {
+        "new"
+    }
+}
+
+/// the state of one side of a session, kept while the websocket is reconnecting
+#[derive(Debug, Default)]
+pub struct SessionState {
+    /// the amount of bytes confirmed to have been written to the tcp stream
+    pub write_cursor: u64,
+    /// the amount of bytes confirmed to have been received by the websocket client
+    pub read_cursor: u64,
+    /// buffer of possibly unreceived bytes, in the array of all bytes returned by the tcp stream these bytes start at `read_cursor`
+    pub buffer: Vec<u8>,
+    /// the stream of this side ended, the other side is told once the buffer is sent
+    pub local_eof: bool,
+    /// the stream of the other side ended, nothing more is received
+    pub remote_eof: bool,
+}
+
+impl SessionState {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(capacity),
+            ..Self::default()
+        }
+    }
+    /// the text message sent when a websocket is attached
+    pub fn attach_message(&self) -> String {
+        self.write_cursor.to_string()
+    }
+    /// bytes read from the stream, kept until the other side confirms them
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+    /// bytes received from the other side and written to the stream
+    pub fn received(&mut self, len: usize) {
+        self.write_cursor += len as u64;
+    }
+    /// forgets the cursors, the buffer and the ends of the streams, returns how many bytes were buffered
+    pub fn reset(&mut self) -> usize {
+        let buffered = self.buffer.len();
+        self.write_cursor = 0;
+        self.read_cursor = 0;
+        self.buffer.clear();
+        self.local_eof = false;
+        self.remote_eof = false;
+        buffered
+    }
+}
+
+/// a text message of the protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// the cursor of the other side
+    Ack(u64),
+    /// the session was closed normally by the other side
+    Close,
+    /// the stream of the other side ended, after all the bytes it sent
+    HalfClose,
+    /// the other side understands `HALF_CLOSE`
+    HalfCloseSupported,
+    /// the first message of the server, if it already had the session
+    Session { resumed: bool },
+}
+
+/// parses a text message, unknown messages are ignored
+pub fn parse_text(text: &str) -> Option<Control> {
+    if text.is_empty() {
+        return Some(Control::Close);
+    }
+    if text == HALF_CLOSE {
+        return Some(Control::HalfClose);
+    }
+    if text == HALF_CLOSE_SUPPORTED {
+        return Some(Control::HalfCloseSupported);
+    }
+    if text == session_message(false) || text == session_message(true) {
+        return Some(Control::Session {
+            resumed: text == session_message(true),
+        });
+    }
+    text.parse().ok().map(Control::Ack)
+}
+
+/// the other side confirmed bytes it could not have received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckError;
+
+impl std::fmt::Display for AckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ack inválido")
+    }
+}
+
+impl std::error::Error for AckError {}
+
+/// the state of a session while attached to one websocket
+#[derive(Debug, Default)]
+pub struct Link {
+    /// how much of the buffer was sent over this websocket, none until the other side sent its cursor
+    sent: Option<usize>,
+    /// if `HALF_CLOSE` was sent over this websocket
+    half_closed: bool,
+    /// if the other side sent `HALF_CLOSE_SUPPORTED` over this websocket
+    peer_half_close: bool,
+}
+
+impl Link {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// the next binary message to send, none until the other side sent its cursor or once all the buffer was sent
+    pub fn next_chunk<'a>(&self, state: &'a SessionState) -> Option<&'a [u8]> {
+        let slice = &state.buffer[self.sent?.min(state.buffer.len())..];
+        if slice.is_empty() {
+            return None;
+        }
+        Some(&slice[..slice.len().min(MAX_BYTES_WS_MESSAGE)])
+    }
+    /// marks the chunk returned by `next_chunk` as sent
+    pub fn advance(&mut self, len: usize) {
+        if let Some(sent) = &mut self.sent {
+            *sent += len;
+        }
+    }
+    /// if the other side sent its cursor and all the buffer was sent to it
+    pub fn is_flushed(&self, state: &SessionState) -> bool {
+        self.sent.is_some_and(|x| x >= state.buffer.len())
+    }
+    /// the other side sent `HALF_CLOSE_SUPPORTED`
+    pub fn half_close_supported(&mut self) {
+        self.peer_half_close = true;
+    }
+    /// if `HALF_CLOSE` must be sent now, once the stream ended and all the buffer was sent
+    /// it is sent again over each websocket, as the other side may have not received it
+    pub fn half_close(&mut self, state: &SessionState) -> bool {
+        if self.half_closed || !self.peer_half_close || !state.local_eof || !self.is_flushed(state)
+        {
+            return false;
+        }
+        self.half_closed = true;
+        true
+    }
+    /// if both streams ended and all the buffer was sent, so the session can be closed
+    /// with a side that does not understand `HALF_CLOSE` it is once the stream of this side ended
+    pub fn is_done(&self, state: &SessionState) -> bool {
+        if self.peer_half_close {
+            self.half_closed && state.remote_eof
+        } else {
+            state.local_eof && self.is_flushed(state)
+        }
+    }
+    /// applies a cursor sent by the other side, returns how many bytes were dropped from the buffer
+    pub fn ack(&mut self, state: &mut SessionState, ack: u64) -> Result<u64, AckError> {
+        if ack < state.read_cursor {
+            return Err(AckError);
+        }
+        let delta = ack - state.read_cursor;
+        if delta > state.buffer.len() as u64 {
+            return Err(AckError);
+        }
+        match &mut self.sent {
+            Some(sent) => {
+                if delta > *sent as u64 {
+                    return Err(AckError);
+                }
+                *sent -= delta as usize;
+            }
+            None => {
+                self.sent = Some(0);
+            }
+        }
+        state.read_cursor = ack;
+        state.buffer.drain(..delta as usize);
+        Ok(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a side with `len` bytes read from its stream, attached to a websocket the other side answered with `ack`
+    fn attached(len: usize, ack: u64) -> (SessionState, Link) {
+        let mut state = SessionState::default();
+        state.push(&vec![7; len]);
+        let mut link = Link::new();
+        link.ack(&mut state, ack).unwrap();
+        (state, link)
+    }
+
+    #[test]
+    fn every_message_is_parsed_back() {
+        let mut state = SessionState::default();
+        assert_eq!(parse_text(&state.attach_message()), Some(Control::Ack(0)));
+        state.received(usize::MAX);
+        assert_eq!(
+            parse_text(&state.attach_message()),
+            Some(Control::Ack(usize::MAX as u64))
+        );
+        assert_eq!(parse_text(""), Some(Control::Close));
+        assert_eq!(parse_text(HALF_CLOSE), Some(Control::HalfClose));
+        assert_eq!(
+            parse_text(HALF_CLOSE_SUPPORTED),
+            Some(Control::HalfCloseSupported)
+        );
+        for resumed in [false, true] {
+            assert_eq!(
+                parse_text(session_message(resumed)),
+                Some(Control::Session { resumed })
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_messages_are_not_understood() {
+        assert_eq!(parse_text("fi"), None);
+        assert_eq!(parse_text("resume"), None);
+        assert_eq!(parse_text("-"), None);
+        assert_eq!(parse_text("18446744073709551616"), None);
+    }
+
+    #[test]
+    fn malformed_messages_are_not_understood() {
+        for text in [" 12", "12 ", "12a", "-1", "1.5", "0x10", "FIN", "fin "] {
+            assert_eq!(parse_text(text), None, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn acks_drop_the_confirmed_bytes() {
+        let (mut state, mut link) = attached(MAX_BYTES_WS_MESSAGE + 10, 0);
+        let chunk = link.next_chunk(&state).unwrap();
+        assert_eq!(chunk.len(), MAX_BYTES_WS_MESSAGE);
+        link.advance(chunk.len());
+        assert_eq!(link.next_chunk(&state).unwrap().len(), 10);
+        link.advance(10);
+        assert!(link.is_flushed(&state) && link.next_chunk(&state).is_none());
+        assert_eq!(link.ack(&mut state, 100), Ok(100));
+        assert_eq!(
+            (state.read_cursor, state.buffer.len()),
+            (100, MAX_BYTES_WS_MESSAGE - 90)
+        );
+        // the same cursor again confirms nothing new
+        assert_eq!(link.ack(&mut state, 100), Ok(0));
+        assert_eq!(
+            link.ack(&mut state, MAX_BYTES_WS_MESSAGE as u64 + 10),
+            Ok(MAX_BYTES_WS_MESSAGE as u64 - 90)
+        );
+        assert!(state.buffer.is_empty());
+    }
+
+    #[test]
+    fn ack_on_reconnect_resends_the_unconfirmed_bytes() {
+        let mut state = SessionState::default();
+        state.push(b"abcdef");
+        let mut link = Link::new();
+        link.ack(&mut state, 0).unwrap();
+        link.advance(link.next_chunk(&state).unwrap().len());
+        // the websocket is lost, the other side had received 2 bytes
+        let mut link = Link::new();
+        assert_eq!(link.next_chunk(&state), None);
+        assert_eq!(link.ack(&mut state, 2), Ok(2));
+        assert_eq!(link.next_chunk(&state), Some(&b"cdef"[..]));
+    }
+
+    #[test]
+    fn nothing_is_sent_before_the_cursor_of_the_other_side() {
+        let mut state = SessionState::default();
+        state.push(b"abc");
+        let link = Link::new();
+        assert_eq!(link.next_chunk(&state), None);
+        assert!(!link.is_flushed(&state));
+    }
+
+    #[test]
+    fn an_ack_in_the_middle_of_a_message_keeps_the_rest_of_it() {
+        let (mut state, mut link) = attached(0, 0);
+        state.push(b"abcdef");
+        link.advance(link.next_chunk(&state).unwrap().len());
+        assert_eq!(link.ack(&mut state, 4), Ok(4));
+        assert_eq!((state.read_cursor, &state.buffer[..]), (4, &b"ef"[..]));
+        // the websocket is lost, only what was not confirmed is sent again
+        let mut link = Link::new();
+        assert_eq!(link.ack(&mut state, 4), Ok(0));
+        assert_eq!(link.next_chunk(&state), Some(&b"ef"[..]));
+    }
+
+    #[test]
+    fn invalid_acks_are_refused_without_changes() {
+        // more than was ever read
+        let mut state = SessionState::default();
+        state.push(b"abc");
+        assert_eq!(Link::new().ack(&mut state, 4), Err(AckError));
+        // more than was sent over this websocket
+        let (mut state, mut link) = attached(10, 0);
+        link.advance(4);
+        assert_eq!(link.ack(&mut state, 5), Err(AckError));
+        // backwards
+        let (mut state, mut link) = attached(10, 6);
+        assert_eq!(state.read_cursor, 6);
+        assert_eq!(link.ack(&mut state, 5), Err(AckError));
+        // nothing changes on an error
+        assert_eq!((state.read_cursor, state.buffer.len()), (6, 4));
+    }
+
+    #[test]
+    fn half_close_is_sent_once_after_everything_was_sent() {
+        let (mut state, mut link) = attached(3, 0);
+        link.half_close_supported();
+        state.local_eof = true;
+        assert!(!link.half_close(&state));
+        link.advance(3);
+        assert!(link.half_close(&state));
+        assert!(!link.half_close(&state));
+        assert!(!link.is_done(&state));
+        state.remote_eof = true;
+        assert!(link.is_done(&state));
+        // sent again over the next websocket
+        let mut link = Link::new();
+        link.half_close_supported();
+        link.ack(&mut state, 3).unwrap();
+        assert!(link.half_close(&state));
+    }
+
+    #[test]
+    fn without_half_close_the_session_is_closed_instead() {
+        let (mut state, mut link) = attached(3, 0);
+        state.local_eof = true;
+        assert!(!link.is_done(&state));
+        link.advance(3);
+        // the other side would ignore it, the session is closed instead
+        assert!(!link.half_close(&state));
+        assert!(link.is_done(&state));
+    }
+}
@@ -0,0 +1,416 @@
+//! the client of the session protocol for browsers, built with `wasm-pack build --target web crates/tow-web`
+//!
+//! browsers can not set headers on websockets, so the parameters of the handshake go in the query string
+
+use std::rc::Rc;
+
+use futures::{
+    channel::mpsc,
+    future::{self, Either},
+    lock::Mutex,
+    StreamExt,
+};
+use tow_protocol::{close_code, Control, Link, SessionState};
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+const DEFAULT_TIMEOUT_MS: u32 = 30_000;
+const RECONNECT_INITIAL_MS: f64 = 500.0;
+const RECONNECT_MAX_MS: f64 = 30_000.0;
+/// how long the first message of the server is waited for after the websocket opens
+const SESSION_MESSAGE_TIMEOUT_MS: f64 = 10_000.0;
+
+/// bytes sent by the service, or why the session failed
+type Received = Result<Vec<u8>, String>;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> JsValue;
+}
+
+/// a session to the tcp service of a server, survives the websocket reconnecting as the tunnel does
+/// the bytes written are sent to the service, the ones the service sends are read
+#[wasm_bindgen]
+pub struct Session {
+    local: mpsc::UnboundedSender<Local>,
+    received: Rc<Mutex<mpsc::UnboundedReceiver<Received>>>,
+}
+
+enum Local {
+    Data(Vec<u8>),
+    Close,
+}
+
+#[wasm_bindgen]
+impl Session {
+    /// opens a session to the websocket of the server at `url`, as `wss://host/path`
+    /// `target` asks the server for a destination as `host:port`, if its target policy allows it
+    /// `timeout_ms` is how long the server keeps the session while the websocket is reconnecting
+    #[wasm_bindgen(constructor)]
+    pub fn new(url: &str, target: Option<String>, timeout_ms: Option<u32>) -> Session {
+        let timeout = timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+        let mut id = 0;
+        while id == 0 {
+            id = (js_sys::Math::random() * (1u64 << 53) as f64) as u64;
+        }
+        let mut url = format!(
+            "{url}{}tow_id={id}&tow_timeout={timeout}",
+            if url.contains('?') { '&' } else { '?' }
+        );
+        if let Some(target) = target {
+            url.push_str(&format!(
+                "&tow_target={}",
+                js_sys::encode_uri_component(&target)
+            ));
+        }
+        let (local, local_receiver) = mpsc::unbounded();
+        let (received_sender, received) = mpsc::unbounded();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = run(url, timeout as f64, local_receiver, &received_sender).await;
+            if let Err(error) = result {
+                let _ = received_sender.unbounded_send(Err(error));
+            }
+        });
+        Session {
+            local,
+            received: Rc::new(Mutex::new(received)),
+        }
+    }
+
+    /// sends the bytes to the service, they are kept until the server confirms them
+    pub fn write(&self, data: &[u8]) -> Result<(), JsError> {
+        self.local
+            .unbounded_send(Local::Data(data.to_vec()))
+            .map_err(|_| JsError::new("a sessão foi encerrada"))
+    }
+
+    /// the next bytes sent by the service, as an `Uint8Array`
+    /// resolves to `undefined` once the session was closed normally and rejects if it failed
+    pub fn read(&self) -> js_sys::Promise {
+        let received = self.received.clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            match received.lock().await.next().await {
+                Some(Ok(data)) => Ok(js_sys::Uint8Array::from(&data[..]).into()),
+                Some(Err(error)) => Err(JsError::new(&error).into()),
+                None => Ok(JsValue::UNDEFINED),
+            }
+        })
+    }
+
+    /// ends the bytes sent to the service once the ones already written are sent, as a tcp half close
+    /// the service may still send bytes, the session is closed once it ends too
+    pub fn close(&self) {
+        let _ = self.local.unbounded_send(Local::Close);
+    }
+}
+
+/// the wait in milliseconds after the nth failed attempt in a row, starting at 1
+/// `random` is in `0.0..1.0` and drops up to half of the wait, as the jitter of the tunnel
+fn reconnect_delay(failures: i32, random: f64) -> f64 {
+    (RECONNECT_INITIAL_MS * 2f64.powi(failures.min(16) - 1)).min(RECONNECT_MAX_MS)
+        * (0.5 + random / 2.0)
+}
+
+/// how the session ended on one websocket
+#[derive(Debug, PartialEq, Eq)]
+enum Detached {
+    /// the session was closed, by either side
+    Closed,
+    /// the websocket was lost, the session continues on the next one
+    Lost,
+}
+
+async fn run(
+    url: String,
+    timeout: f64,
+    mut local: mpsc::UnboundedReceiver<Local>,
+    received: &mpsc::UnboundedSender<Received>,
+) -> Result<(), String> {
+    let mut state = SessionState::with_capacity(1024 * 4);
+    // if a websocket was connected before, so the server must resume the session
+    let mut connected = false;
+    let mut last_connect = js_sys::Date::now();
+    // failed attempts since the last websocket, the first attempt after losing one is immediate
+    let mut failures = 0;
+    loop {
+        let timeout = (timeout - (js_sys::Date::now() - last_connect)).max(0.0);
+        let opened = match Socket::open(&url).await {
+            Ok(mut socket) => session_message(&mut socket)
+                .await
+                .map(|resumed| (socket, resumed)),
+            Err(error) => Err(error),
+        };
+        let mut socket = match opened {
+            Ok((socket, resumed)) if resumed != connected => {
+                // a new session on a reconnect lost the bytes in flight, a resumed one on the first connect is of
+                // another client, and is closed without a reason, that would also close it
+                if connected {
+                    socket.close(close_code::SESSION_LOST, "session-lost");
+                }
+                return Err(format!(
+                    "o servidor perdeu a sessão ({})",
+                    close_code::SESSION_LOST
+                ));
+            }
+            Ok((socket, _)) => socket,
+            Err(error) => {
+                failures += 1;
+                if timeout == 0.0 {
+                    return Err(format!(
+                        "não foi possível conectar no servidor após {failures} tentativas: {error}"
+                    ));
+                }
+                // never waits past the timeout, so the last attempt is still made before the server forgets the session
+                let delay = reconnect_delay(failures, js_sys::Math::random());
+                sleep(delay.min(timeout)).await;
+                continue;
+            }
+        };
+        failures = 0;
+        connected = true;
+        match attach(&mut socket, &mut state, &mut local, received).await? {
+            Detached::Closed => return Ok(()),
+            Detached::Lost => last_connect = js_sys::Date::now(),
+        }
+    }
+}
+
+/// reads the first message of the server, that tells if it already had the session
+async fn session_message(socket: &mut Socket) -> Result<bool, String> {
+    let timeout = sleep(SESSION_MESSAGE_TIMEOUT_MS);
+    futures::pin_mut!(timeout);
+    let event = match future::select(socket.events.next(), timeout).await {
+        Either::Left((event, _)) => event,
+        Either::Right(_) => return Err("o servidor não informou a sessão a tempo".to_owned()),
+    };
+    match event {
+        Some(Event::Text(text)) => match tow_protocol::parse_text(&text) {
+            Some(Control::Session { resumed }) => Ok(resumed),
+            _ => Err("o servidor não informou a sessão".to_owned()),
+        },
+        Some(Event::Close(code, _)) => Err(format!("websocket fechado ({code})")),
+        _ => Err("websocket fechado".to_owned()),
+    }
+}
+
+/// runs the session over one websocket, as `try_handle_live_session` of the tunnel
+async fn attach(
+    socket: &mut Socket,
+    state: &mut SessionState,
+    local: &mut mpsc::UnboundedReceiver<Local>,
+    received: &mpsc::UnboundedSender<Received>,
+) -> Result<Detached, String> {
+    for text in [tow_protocol::HALF_CLOSE_SUPPORTED, &state.attach_message()] {
+        if socket.send_text(text).is_err() {
+            return Ok(Detached::Lost);
+        }
+    }
+    let mut link = Link::new();
+    loop {
+        while let Some(chunk) = link.next_chunk(state) {
+            if socket.send_binary(chunk).is_err() {
+                return Ok(Detached::Lost);
+            }
+            link.advance(chunk.len());
+        }
+        if link.half_close(state) && socket.send_text(tow_protocol::HALF_CLOSE).is_err() {
+            return Ok(Detached::Lost);
+        }
+        if link.is_done(state) {
+            let _ = socket.send_text("");
+            return Ok(Detached::Closed);
+        }
+        let next = {
+            let local_next = async {
+                if state.local_eof {
+                    future::pending().await
+                } else {
+                    local.next().await
+                }
+            };
+            futures::pin_mut!(local_next);
+            match future::select(local_next, socket.events.next()).await {
+                Either::Left((x, _)) => Either::Left(x),
+                Either::Right((x, _)) => Either::Right(x),
+            }
+        };
+        match next {
+            Either::Left(Some(Local::Data(data))) => state.push(&data),
+            Either::Left(Some(Local::Close) | None) => state.local_eof = true,
+            Either::Right(Some(Event::Binary(data))) => {
+                state.received(data.len());
+                let _ = received.unbounded_send(Ok(data));
+            }
+            Either::Right(Some(Event::Text(text))) => match tow_protocol::parse_text(&text) {
+                Some(Control::Close) => return Ok(Detached::Closed),
+                Some(Control::HalfClose) => {
+                    // `read` resolves to `undefined` once the bytes already received are read
+                    state.remote_eof = true;
+                    received.close_channel();
+                }
+                Some(Control::HalfCloseSupported) => link.half_close_supported(),
+                Some(Control::Ack(ack)) => {
+                    link.ack(state, ack).map_err(|error| error.to_string())?;
+                }
+                Some(Control::Session { .. }) | None => {}
+            },
+            Either::Right(Some(Event::Close(code, reason))) => return detached_by(code, &reason),
+            Either::Right(Some(Event::Open)) => {}
+            Either::Right(None) => return Ok(Detached::Lost),
+        }
+    }
+}
+
+/// how a close frame of the server ends the session on the websocket
+fn detached_by(code: u16, reason: &str) -> Result<Detached, String> {
+    match code {
+        // the idle timeout and the maximum lifetime are not errors
+        close_code::IDLE_TIMEOUT | close_code::MAX_LIFETIME => Ok(Detached::Closed),
+        4000..=4999 => Err(format!("sessão encerrada pelo servidor: {reason} ({code})")),
+        _ => Ok(Detached::Lost),
+    }
+}
+
+enum Event {
+    Open,
+    Binary(Vec<u8>),
+    Text(String),
+    Close(u16, String),
+}
+
+/// a browser websocket, with its callbacks turned into a stream of events
+struct Socket {
+    ws: WebSocket,
+    events: mpsc::UnboundedReceiver<Event>,
+    _on_open: Closure<dyn FnMut(web_sys::Event)>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+impl Socket {
+    /// connects the websocket, the browser does not tell why a connection failed
+    async fn open(url: &str) -> Result<Socket, String> {
+        let ws = WebSocket::new(url).map_err(describe)?;
+        ws.set_binary_type(BinaryType::Arraybuffer);
+        let (sender, events) = mpsc::unbounded();
+        let on_open = {
+            let sender = sender.clone();
+            Closure::<dyn FnMut(web_sys::Event)>::new(move |_| {
+                let _ = sender.unbounded_send(Event::Open);
+            })
+        };
+        let on_message = {
+            let sender = sender.clone();
+            Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                let data = event.data();
+                let event = match data.dyn_into::<js_sys::ArrayBuffer>() {
+                    Ok(buffer) => Event::Binary(js_sys::Uint8Array::new(&buffer).to_vec()),
+                    Err(data) => Event::Text(data.as_string().unwrap_or_default()),
+                };
+                let _ = sender.unbounded_send(event);
+            })
+        };
+        let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+            let _ = sender.unbounded_send(Event::Close(event.code(), event.reason()));
+        });
+        ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        let mut socket = Socket {
+            ws,
+            events,
+            _on_open: on_open,
+            _on_message: on_message,
+            _on_close: on_close,
+        };
+        match socket.events.next().await {
+            Some(Event::Open) => Ok(socket),
+            Some(Event::Close(code, _)) => Err(format!("websocket fechado ({code})")),
+            _ => Err("websocket fechado".to_owned()),
+        }
+    }
+    fn send_text(&self, text: &str) -> Result<(), String> {
+        self.ws.send_with_str(text).map_err(describe)
+    }
+    fn send_binary(&self, data: &[u8]) -> Result<(), String> {
+        self.ws.send_with_u8_array(data).map_err(describe)
+    }
+    fn close(&self, code: u16, reason: &str) {
+        let _ = self.ws.close_with_code_and_reason(code, reason);
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        self.ws.set_onopen(None);
+        self.ws.set_onmessage(None);
+        self.ws.set_onclose(None);
+        let _ = self.ws.close();
+    }
+}
+
+fn describe(error: JsValue) -> String {
+    error
+        .dyn_ref::<js_sys::Error>()
+        .map(|x| String::from(x.message()))
+        .unwrap_or_else(|| format!("{error:?}"))
+}
+
+async fn sleep(ms: f64) {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        set_timeout(&resolve, ms as i32);
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_wait_doubles_until_the_max() {
+        let waits = (1..=8).map(|x| reconnect_delay(x, 1.0)).collect::<Vec<_>>();
+        assert_eq!(
+            waits,
+            [500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0, 30000.0, 30000.0]
+        );
+        assert_eq!(reconnect_delay(i32::MAX, 1.0), RECONNECT_MAX_MS);
+    }
+
+    #[test]
+    fn the_jitter_drops_up_to_half_the_wait() {
+        assert_eq!(reconnect_delay(1, 0.0), 250.0);
+        assert_eq!(reconnect_delay(1, 0.5), 375.0);
+        assert!(reconnect_delay(1, 0.999) < 500.0);
+        assert_eq!(reconnect_delay(7, 0.0), 15000.0);
+    }
+
+    #[test]
+    fn expired_sessions_are_closed_normally() {
+        assert_eq!(
+            detached_by(close_code::IDLE_TIMEOUT, "idle-timeout"),
+            Ok(Detached::Closed)
+        );
+        assert_eq!(
+            detached_by(close_code::MAX_LIFETIME, "max-lifetime"),
+            Ok(Detached::Closed)
+        );
+    }
+
+    #[test]
+    fn the_other_reasons_of_the_server_are_errors() {
+        assert_eq!(
+            detached_by(4001, "backend-refused"),
+            Err("sessão encerrada pelo servidor: backend-refused (4001)".to_owned())
+        );
+        assert!(detached_by(4004, "killed").is_err());
+    }
+
+    #[test]
+    fn other_closes_only_lose_the_websocket() {
+        for code in [1000, 1001, 1006, 1011] {
+            assert_eq!(detached_by(code, ""), Ok(Detached::Lost), "{code}");
+        }
+    }
+}
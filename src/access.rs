@@ -0,0 +1,290 @@
+use std::net::IpAddr;
+
+use async_tungstenite::tungstenite::http::HeaderMap;
+
+/// an ip network, as in `10.0.0.0/8`, a single ip is a network with the full prefix
+#[derive(Debug, Clone, Copy)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = ();
+    fn from_str(text: &str) -> Result<Self, ()> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+        let addr = canonical(addr.parse::<IpAddr>().map_err(|_| ())?);
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| ())?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            return Err(());
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// parses a list of networks separated by (;), (,) or spaces, invalid ones are logged and ignored
+pub fn parse_many_cidr(text: &str) -> Vec<Cidr> {
+    text.split([';', ',', ' '])
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .filter_map(|x| match x.parse() {
+            Ok(cidr) => Some(cidr),
+            Err(()) => {
+                tracing::warn!("rede inválida: {x:?}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// which ips may connect to a listener
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    /// if not empty, only these networks are allowed
+    pub allow: Vec<Cidr>,
+    /// always refused, even if also in `allow`
+    pub deny: Vec<Cidr>,
+    /// proxies whose `X-Forwarded-For` and `Forwarded` headers are believed
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl AccessList {
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|x| x.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|x| x.contains(ip)))
+    }
+    pub fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|x| x.contains(ip))
+    }
+    /// the ip of the client, following the forwarding headers from right to left while the hop is a trusted proxy
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted_proxy(peer) {
+            return peer;
+        }
+        let mut hops = forwarded_for(headers);
+        let mut ip = peer;
+        while self.is_trusted_proxy(ip) {
+            match hops.pop() {
+                Some(hop) => ip = hop,
+                None => break,
+            }
+        }
+        ip
+    }
+}
+
+/// the ips in the `Forwarded` header, or in the `X-Forwarded-For` header if there is none, from the client to the last proxy
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    let forwarded = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .filter_map(|x| {
+            x.split(';')
+                .filter_map(|x| x.trim().split_once('='))
+                .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                .map(|(_, value)| value)
+        })
+        .map(parse_hop)
+        .collect::<Vec<_>>();
+    let hops = if forwarded.is_empty() {
+        headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(','))
+            .map(parse_hop)
+            .collect()
+    } else {
+        forwarded
+    };
+    // an unparseable hop, as `unknown` or an obfuscated identifier, cuts the chain there
+    match hops.iter().rposition(Option::is_none) {
+        Some(index) => hops[index + 1..].iter().flatten().copied().collect(),
+        None => hops.into_iter().flatten().collect(),
+    }
+}
+
+/// parses `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1`, `"[2001:db8::1]:80"`
+fn parse_hop(text: &str) -> Option<IpAddr> {
+    let text = text.trim().trim_matches('"');
+    if let Ok(ip) = text.parse::<IpAddr>() {
+        return Some(canonical(ip));
+    }
+    if let Ok(addr) = text.parse::<std::net::SocketAddr>() {
+        return Some(canonical(addr.ip()));
+    }
+    let text = text.strip_prefix('[').and_then(|x| x.strip_suffix(']'))?;
+    text.parse::<IpAddr>().ok().map(canonical)
+}
+
+/// ipv4 addresses mapped in ipv6, as accepted by dual stack sockets, are compared as ipv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_tungstenite::tungstenite::http::HeaderValue;
+
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn access_list(allow: &str, deny: &str, trusted_proxies: &str) -> AccessList {
+        AccessList {
+            allow: parse_many_cidr(allow),
+            deny: parse_many_cidr(deny),
+            trusted_proxies: parse_many_cidr(trusted_proxies),
+        }
+    }
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let list = access_list("10.0.0.0/8", "10.1.0.0/16", "");
+        assert!(list.is_allowed(ip("10.2.0.1")));
+        assert!(!list.is_allowed(ip("10.1.0.1")));
+        assert!(!list.is_allowed(ip("192.168.0.1")));
+        // mapped addresses are the same client as the ipv4 one
+        assert!(!list.is_allowed(ip("::ffff:10.1.0.1")));
+        assert!(list.is_allowed(ip("::ffff:10.2.0.1")));
+    }
+
+    #[test]
+    fn an_empty_allow_list_allows_everything_not_denied() {
+        let list = access_list("", "203.0.113.0/24", "");
+        assert!(list.is_allowed(ip("198.51.100.1")));
+        assert!(list.is_allowed(ip("::1")));
+        assert!(!list.is_allowed(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn the_headers_of_untrusted_peers_are_ignored() {
+        let list = access_list("", "", "10.0.0.0/8");
+        let headers = headers(&[
+            ("x-forwarded-for", "198.51.100.1"),
+            ("forwarded", "for=198.51.100.2"),
+        ]);
+        assert_eq!(
+            list.client_ip(ip("203.0.113.7"), &headers),
+            ip("203.0.113.7")
+        );
+        // nothing is believed without trusted proxies
+        let list = access_list("", "", "");
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &headers), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn hops_are_followed_from_the_right_while_they_are_trusted() {
+        let list = access_list("", "", "10.0.0.0/8");
+        // the client may put anything on the left, only what the trusted proxies appended counts
+        let header = headers(&[(
+            "x-forwarded-for",
+            "1.1.1.1, 203.0.113.7, 198.51.100.2, 10.0.0.2",
+        )]);
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &header), ip("198.51.100.2"));
+        // a header per proxy is the same as a single list
+        let header = headers(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-for", "10.0.0.3, 10.0.0.2"),
+        ]);
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &header), ip("203.0.113.7"));
+        // only trusted proxies, the first one is the client
+        let header = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &header), ip("10.0.0.3"));
+        // no header, the proxy itself is the client
+        assert_eq!(
+            list.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded_for() {
+        let list = access_list("", "", "10.0.0.0/8");
+        let header = headers(&[
+            ("x-forwarded-for", "198.51.100.9"),
+            (
+                "forwarded",
+                "for=192.0.2.60;proto=http, For=\"[2001:db8::1]:4711\";by=10.0.0.2",
+            ),
+        ]);
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &header), ip("2001:db8::1"));
+        let header = headers(&[("forwarded", "for=\"::ffff:10.0.0.2\", for=10.0.0.3:80")]);
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &header), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn an_unknown_hop_cuts_the_chain() {
+        let list = access_list("", "", "10.0.0.0/8");
+        // what is left of the unknown hop can not be believed, the last known hop is the client
+        let header = headers(&[("x-forwarded-for", "203.0.113.7, unknown, 10.0.0.2")]);
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &header), ip("10.0.0.2"));
+        let header = headers(&[("forwarded", "for=203.0.113.7, for=_hidden")]);
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &header), ip("10.0.0.1"));
+        let header = headers(&[("forwarded", "for=unknown, for=198.51.100.3")]);
+        assert_eq!(list.client_ip(ip("10.0.0.1"), &header), ip("198.51.100.3"));
+    }
+
+    #[test]
+    fn networks_contain_their_addresses() {
+        let network: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(network.contains(ip("10.1.255.3")));
+        assert!(network.contains(ip("::ffff:10.1.0.1")));
+        assert!(!network.contains(ip("10.2.0.1")));
+        assert!(!network.contains(ip("::1")));
+        assert!(parse_many_cidr("127.0.0.1")[0].contains(ip("127.0.0.1")));
+        assert!(!parse_many_cidr("127.0.0.1")[0].contains(ip("127.0.0.2")));
+        // invalid networks are left out of the list instead of allowing or denying more than written
+        assert_eq!(
+            parse_many_cidr("10.0.0.0/33, fd00::/8; exemplo.com 10.0.0.0/x").len(),
+            1
+        );
+    }
+}
@@ -29,10 +29,218 @@ fn parse_socket_addr(text: &str) -> [Option<SocketAddr>; 2] {
         [
             text.parse()
                 .map_err(|_| {
-                    println!("o endereço {text:?} não é válido");
+                    tracing::warn!("o endereço {text:?} não é válido");
                 })
                 .ok(),
             None,
         ]
     }
 }
+
+/// an address to connect to, the host may be an ip or a hostname, it is only resolved at connect time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+}
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+pub fn parse_many_target(text: &str) -> Vec<Target> {
+    text.split([';', ',', ' '])
+        .filter(|x| !x.trim().is_empty())
+        .flat_map(parse_target)
+        .flatten()
+        .collect::<Vec<_>>()
+}
+pub fn parse_one_target(text: &str) -> Option<Target> {
+    parse_target(text).into_iter().flatten().next()
+}
+
+/// a bare port is both localhost addresses, as in `parse_socket_addr`
+fn parse_target(text: &str) -> [Option<Target>; 2] {
+    let text = text.trim();
+    if text.is_empty() {
+        return [None, None];
+    }
+    let addrs = parse_socket_addr_quiet(text);
+    if addrs[0].is_some() {
+        return addrs.map(|addr| {
+            addr.map(|addr| Target {
+                host: addr.ip().to_string(),
+                port: addr.port(),
+            })
+        });
+    }
+    let target = text
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        .filter(|(host, _)| {
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | '-' | '_'))
+        })
+        .map(|(host, port)| Target {
+            host: host.to_owned(),
+            port,
+        });
+    if target.is_none() {
+        tracing::warn!("o endereço {text:?} não é válido");
+    }
+    [target, None]
+}
+
+/// the same as `parse_socket_addr`, without logging invalid addresses, that may still be names
+fn parse_socket_addr_quiet(text: &str) -> [Option<SocketAddr>; 2] {
+    match text.parse::<u16>() {
+        Ok(port) => [
+            Some(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port))),
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::LOCALHOST,
+                port,
+                0,
+                0,
+            ))),
+        ],
+        Err(_) => [text.parse().ok(), None],
+    }
+}
+
+/// a unix socket path prefixed with `unix:`, `None` if the text is not one
+fn parse_unix_path(text: &str) -> Option<Result<std::path::PathBuf, ()>> {
+    let path = text.trim().strip_prefix("unix:")?;
+    if path.is_empty() {
+        tracing::warn!("o endereço {text:?} não é válido");
+        return Some(Err(()));
+    }
+    #[cfg(unix)]
+    return Some(Ok(path.into()));
+    #[cfg(not(unix))]
+    {
+        tracing::error!("sockets unix não são suportados nesse sistema: {path:?}");
+        Some(Err(()))
+    }
+}
+
+/// where a local listener binds, either a list of addresses or a unix socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(Vec<SocketAddr>),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(addrs) => {
+                let addrs = addrs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                f.write_str(&addrs.join(";"))
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// parses either a list of addresses, as `parse_many_socket_addr`, or a path to a unix socket prefixed with `unix:`
+pub fn parse_listen_addr(text: &str) -> Option<ListenAddr> {
+    match parse_unix_path(text) {
+        #[cfg(unix)]
+        Some(path) => path.ok().map(ListenAddr::Unix),
+        #[cfg(not(unix))]
+        Some(_) => None,
+        None => {
+            let addrs = parse_many_socket_addr(text);
+            (!addrs.is_empty()).then_some(ListenAddr::Tcp(addrs))
+        }
+    }
+}
+
+/// a service to connect to, a `Target` or a unix socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(Target),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp(target) => target.fmt(f),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+pub fn parse_many_endpoint(text: &str) -> Vec<Endpoint> {
+    text.split([';', ',', ' '])
+        .filter(|x| !x.trim().is_empty())
+        .flat_map(parse_endpoint)
+        .flatten()
+        .collect::<Vec<_>>()
+}
+/// parses either a target, as `parse_one_target`, or a path to a unix socket prefixed with `unix:`
+///
+/// a bare port is both localhost addresses, as in `parse_many_endpoint`, so one endpoint may give two
+pub fn parse_one_endpoint(text: &str) -> Vec<Endpoint> {
+    parse_endpoint(text).into_iter().flatten().collect()
+}
+
+fn parse_endpoint(text: &str) -> [Option<Endpoint>; 2] {
+    match parse_unix_path(text) {
+        #[cfg(unix)]
+        Some(path) => [path.ok().map(Endpoint::Unix), None],
+        #[cfg(not(unix))]
+        Some(_) => [None, None],
+        None => parse_target(text).map(|x| x.map(Endpoint::Tcp)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(host: &str, port: u16) -> Endpoint {
+        Endpoint::Tcp(Target {
+            host: host.to_owned(),
+            port,
+        })
+    }
+
+    #[test]
+    fn bare_ports_are_both_localhost_addresses() {
+        assert_eq!(
+            parse_many_endpoint("19260; db2.internal:5432"),
+            [
+                tcp("127.0.0.1", 19260),
+                tcp("::1", 19260),
+                tcp("db2.internal", 5432)
+            ]
+        );
+        assert_eq!(
+            parse_many_target("19260")
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            parse_many_socket_addr("19260")
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(parse_one_endpoint("19260"), parse_many_endpoint("19260"));
+        assert_eq!(
+            parse_one_endpoint("db2.internal:5432"),
+            [tcp("db2.internal", 5432)]
+        );
+        assert_eq!(parse_one_endpoint("db2.internal"), []);
+    }
+}
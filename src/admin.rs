@@ -0,0 +1,486 @@
+use std::{
+    io::{Read, Write},
+    net::SocketAddr,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    close, errors, health::Health, httpd, metrics, shaping::Shaping, tunnel, Direction,
+    ServerState, SessionEntry,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// where the admin api is served, it has no authentication so it should only be reachable locally
+pub enum AdminListen {
+    Tcp(Vec<SocketAddr>),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl std::str::FromStr for AdminListen {
+    type Err = ();
+    /// parses either a list of addresses, or a path to a unix socket prefixed with `unix:`
+    fn from_str(text: &str) -> Result<Self, ()> {
+        if let Some(path) = text.trim().strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(AdminListen::Unix(path.into()));
+            #[cfg(not(unix))]
+            {
+                tracing::error!("sockets unix não são suportados nesse sistema: {path:?}");
+                return Err(());
+            }
+        }
+        let addrs = crate::addr::parse_many_socket_addr(text);
+        if addrs.is_empty() {
+            return Err(());
+        }
+        for addr in &addrs {
+            if !addr.ip().is_loopback() {
+                tracing::warn!(
+                    "a api de admin em {addr} não é local, qualquer um pode encerrar sessões"
+                );
+            }
+        }
+        Ok(AdminListen::Tcp(addrs))
+    }
+}
+
+/// the response of `GET /status`
+#[derive(Serialize, Deserialize)]
+pub struct Status {
+    pub draining: bool,
+    pub uptime_secs: u64,
+    pub listen: Option<SocketAddr>,
+    pub sessions: usize,
+    /// sessions with a websocket attached
+    pub attached_sessions: usize,
+    pub bytes_tcp_to_ws: u64,
+    pub bytes_ws_to_tcp: u64,
+    /// the bandwidth limits of all the sessions added
+    pub shaping: Shaping,
+    /// the bandwidth limits of each session
+    pub session_shaping: Shaping,
+    pub backends: Vec<BackendStatus>,
+    /// the client tunnels running in the same process
+    pub tunnels: Vec<TunnelInfo>,
+    /// the newest first
+    pub recent_errors: Vec<ErrorInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BackendStatus {
+    pub target: String,
+    pub weight: u32,
+    pub up: bool,
+    pub health: Health,
+    pub active_sessions: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TunnelInfo {
+    pub remote: String,
+    pub listen: Option<String>,
+    pub sessions: usize,
+    pub bytes_tcp_to_ws: u64,
+    pub bytes_ws_to_tcp: u64,
+    pub shaping: Shaping,
+    pub session_shaping: Shaping,
+    #[serde(default)]
+    pub reconnects: u64,
+    #[serde(default)]
+    pub failed_connects: u64,
+}
+
+impl TunnelInfo {
+    pub fn new(tunnel: &tunnel::TunnelStatus) -> Self {
+        Self {
+            remote: tunnel.remote.clone(),
+            listen: tunnel.listen.clone(),
+            sessions: tunnel.sessions(),
+            bytes_tcp_to_ws: tunnel.bytes_tcp_to_ws(),
+            bytes_ws_to_tcp: tunnel.bytes_ws_to_tcp(),
+            shaping: tunnel.tunnel_shaping(),
+            session_shaping: tunnel.session_shaping,
+            reconnects: tunnel.reconnects(),
+            failed_connects: tunnel.failed_connects(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ErrorInfo {
+    pub age_secs: u64,
+    pub level: String,
+    pub session: Option<String>,
+    pub message: String,
+}
+
+/// the response of `GET /sessions/{id}`, and of each item of `GET /sessions`
+#[derive(Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// in hex, the same as in the logs
+    pub id: String,
+    pub direction: Direction,
+    pub peer: Option<SocketAddr>,
+    pub target: Option<SocketAddr>,
+    #[serde(default)]
+    pub requested_target: Option<String>,
+    pub write_cursor: u64,
+    pub read_cursor: u64,
+    pub buffered: u64,
+    pub attached: bool,
+    pub closed: bool,
+    pub age_ms: u64,
+    pub idle_ms: u64,
+}
+
+impl SessionInfo {
+    fn new(id: u64, entry: &SessionEntry) -> Self {
+        let status = &entry.status;
+        Self {
+            id: format!("{id:016x}"),
+            direction: status.dir,
+            peer: status.peer(),
+            target: status.target(),
+            requested_target: status.requested_target(),
+            write_cursor: status.write_cursor(),
+            read_cursor: status.read_cursor(),
+            buffered: status.buffered(),
+            attached: status.attached(),
+            closed: status.closed(),
+            age_ms: status.created.elapsed().as_millis() as u64,
+            idle_ms: if status.attached() {
+                0
+            } else {
+                status.last_use().elapsed().as_millis() as u64
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorJson {
+    error: &'static str,
+}
+
+/// binds the admin api and serves it in the background
+pub(crate) async fn spawn(listen: AdminListen, state: &'static ServerState) -> std::io::Result<()> {
+    let handler = move |request: httpd::Request| handle(request, state);
+    match listen {
+        AdminListen::Tcp(addrs) => {
+            let listener = tokio::net::TcpListener::bind(&addrs[..]).await?;
+            tokio::spawn(httpd::serve(listener, handler));
+        }
+        #[cfg(unix)]
+        AdminListen::Unix(path) => {
+            // a socket left behind by a previous run would make the bind fail
+            if std::fs::symlink_metadata(&path)
+                .is_ok_and(|x| std::os::unix::fs::FileTypeExt::is_socket(&x.file_type()))
+            {
+                let _ = std::fs::remove_file(&path);
+            }
+            let listener = tokio::net::UnixListener::bind(&path)?;
+            tokio::spawn(httpd::serve_unix(listener, handler));
+        }
+    }
+    Ok(())
+}
+
+async fn handle(request: httpd::Request, state: &'static ServerState) -> httpd::Response {
+    let segments = request
+        .path
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    match (request.method.as_str(), &segments[..]) {
+        ("GET", ["status"]) => httpd::Response::json(200, &status(state).await),
+        ("GET", ["sessions"]) => {
+            let sessions = state
+                .sessions
+                .read()
+                .await
+                .iter()
+                .map(|(id, entry)| SessionInfo::new(*id, entry))
+                .collect::<Vec<_>>();
+            httpd::Response::json(200, &sessions)
+        }
+        ("GET", ["sessions", id]) => match find(state, id).await {
+            Some((id, entry)) => httpd::Response::json(200, &SessionInfo::new(id, &entry)),
+            None => session_not_found(),
+        },
+        ("DELETE", ["sessions", id]) | ("POST", ["sessions", id, "kill"]) => {
+            match find(state, id).await {
+                Some((id, entry)) => {
+                    kill(&entry);
+                    tracing::info!(id = format!("{id:016x}"), "sessão encerrada pelo admin");
+                    httpd::Response::json(200, &SessionInfo::new(id, &entry))
+                }
+                None => session_not_found(),
+            }
+        }
+        ("POST", ["drain"]) => {
+            if !state.draining.swap(true, Ordering::Relaxed) {
+                tracing::info!("novas sessões não serão mais aceitas");
+            }
+            httpd::Response::json(200, &status(state).await)
+        }
+        ("POST", ["undrain"]) => {
+            if state.draining.swap(false, Ordering::Relaxed) {
+                tracing::info!("novas sessões voltaram a ser aceitas");
+            }
+            httpd::Response::json(200, &status(state).await)
+        }
+        (_, ["status" | "sessions" | "drain" | "undrain", ..]) => {
+            httpd::Response::method_not_allowed()
+        }
+        _ => httpd::Response::not_found(),
+    }
+}
+
+async fn status(state: &'static ServerState) -> Status {
+    let (sessions, attached_sessions) = {
+        let sessions = state.sessions.read().await;
+        let attached = sessions.values().filter(|x| x.status.attached()).count();
+        (sessions.len(), attached)
+    };
+    Status {
+        draining: state.draining.load(Ordering::Relaxed),
+        uptime_secs: Instant::now().duration_since(state.started).as_secs(),
+        listen: state.local_addr,
+        sessions,
+        attached_sessions,
+        bytes_tcp_to_ws: metrics::get(&metrics::BYTES_TCP_TO_WS),
+        bytes_ws_to_tcp: metrics::get(&metrics::BYTES_WS_TO_TCP),
+        shaping: state.shaper.as_ref().map(|x| x.shaping).unwrap_or_default(),
+        session_shaping: state.session_shaping,
+        backends: state
+            .pool
+            .backends
+            .iter()
+            .map(|x| BackendStatus {
+                target: x.target.to_string(),
+                weight: x.weight,
+                up: x.is_healthy(),
+                health: x.health(),
+                active_sessions: x.active(),
+            })
+            .collect(),
+        tunnels: tunnel::tunnels()
+            .iter()
+            .map(|x| TunnelInfo::new(x))
+            .collect(),
+        recent_errors: errors::recent_errors()
+            .into_iter()
+            .map(|x| ErrorInfo {
+                age_secs: x.age().as_secs(),
+                level: x.level.to_string(),
+                session: x.session,
+                message: x.message,
+            })
+            .collect(),
+    }
+}
+
+/// the id is in hex, as shown in the logs and in the api
+async fn find(state: &'static ServerState, id: &str) -> Option<(u64, SessionEntry)> {
+    let id = u64::from_str_radix(id, 16).ok()?;
+    let entry = state.sessions.read().await.get(&id)?.clone();
+    Some((id, entry))
+}
+
+/// closes the session, if a websocket is attached it is told to close it, otherwise it is closed here
+fn kill(entry: &SessionEntry) {
+    match entry.session.try_lock() {
+        Ok(mut session) => session.close(close::CloseReason::Killed.as_str()),
+        Err(_) => entry.status.kill.notify_one(),
+    }
+}
+
+fn session_not_found() -> httpd::Response {
+    httpd::Response::json(
+        404,
+        &ErrorJson {
+            error: "sessão não encontrada",
+        },
+    )
+}
+
+/// makes a request to the admin api of a running instance, blocking, returns the status and the body of the response
+pub fn request(listen: &AdminListen, method: &str, path: &str) -> std::io::Result<(u16, Vec<u8>)> {
+    let head = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    let mut response = Vec::new();
+    match listen {
+        AdminListen::Tcp(addrs) => {
+            let mut stream = connect_any(addrs)?;
+            stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+            stream.write_all(head.as_bytes())?;
+            stream.read_to_end(&mut response)?;
+        }
+        #[cfg(unix)]
+        AdminListen::Unix(path) => {
+            let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+            stream.write_all(head.as_bytes())?;
+            stream.read_to_end(&mut response)?;
+        }
+    }
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "resposta http inválida");
+    let head_len = response
+        .windows(4)
+        .position(|x| x == b"\r\n\r\n")
+        .ok_or_else(invalid)?;
+    let status = std::str::from_utf8(&response[..head_len])
+        .ok()
+        .and_then(|x| x.split(' ').nth(1))
+        .and_then(|x| x.parse::<u16>().ok())
+        .ok_or_else(invalid)?;
+    Ok((status, response.split_off(head_len + 4)))
+}
+
+/// connects to the first of the addresses of the admin api that accepts, blocking
+fn connect_any(addrs: &[std::net::SocketAddr]) -> std::io::Result<std::net::TcpStream> {
+    let mut last_error = None;
+    for addr in addrs {
+        match std::net::TcpStream::connect_timeout(addr, REQUEST_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "nenhum endereço da api de administração",
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use async_tungstenite::tungstenite::{client::IntoClientRequest, http};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        close::CloseReason,
+        tests::{close_reason, pool_of, serve, server_state, target_of},
+    };
+
+    /// sends a request to the admin api, returns its status and its json
+    async fn call(
+        state: &'static ServerState,
+        method: &str,
+        path: &str,
+    ) -> (u16, serde_json::Value) {
+        let request = httpd::Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            query: String::new(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        let response = handle(request, state).await;
+        let json = serde_json::from_slice(&response.body).unwrap_or_default();
+        (response.status, json)
+    }
+
+    #[tokio::test]
+    async fn draining_is_turned_on_and_off() {
+        let state = server_state(pool_of(target_of(
+            &TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        )));
+        let (status, json) = call(state, "GET", "/status").await;
+        assert_eq!(status, 200);
+        assert_eq!(json["draining"], false);
+        assert_eq!(json["sessions"], 0);
+        assert_eq!(json["backends"][0]["up"], true);
+        let (_, json) = call(state, "POST", "/drain").await;
+        assert_eq!(json["draining"], true);
+        assert!(state.draining.load(Ordering::Relaxed));
+        let (_, json) = call(state, "POST", "/undrain").await;
+        assert_eq!(json["draining"], false);
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_methods_and_sessions_are_refused() {
+        let state = server_state(pool_of(target_of(
+            &TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        )));
+        assert_eq!(call(state, "GET", "/drain").await.0, 405);
+        assert_eq!(call(state, "PUT", "/sessions").await.0, 405);
+        assert_eq!(call(state, "GET", "/").await.0, 404);
+        assert_eq!(
+            call(state, "GET", "/sessions").await.1,
+            serde_json::json!([])
+        );
+        assert_eq!(call(state, "GET", "/sessions/zz").await.0, 404);
+        assert_eq!(call(state, "DELETE", "/sessions/1234").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn a_killed_session_is_closed_with_its_reason() {
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let state = server_state(pool_of(target_of(&backend)));
+        let mut request = serve(state).await.into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("x-tow-id", http::HeaderValue::from_static("4660"));
+        let (mut websocket, _) = async_tungstenite::tokio::connect_async(request)
+            .await
+            .unwrap();
+        let _backend = backend.accept().await.unwrap();
+        // attached once the session is locked by its websocket
+        loop {
+            let (status, json) = call(state, "GET", "/sessions/0000000000001234").await;
+            if status == 200 && json["attached"] == true {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let (status, json) = call(state, "DELETE", "/sessions/0000000000001234").await;
+        assert_eq!(status, 200);
+        assert_eq!(json["id"], "0000000000001234");
+        assert_eq!(
+            close_reason(&mut websocket).await,
+            Some(CloseReason::Killed)
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_try_every_address() {
+        let state = server_state(pool_of(target_of(
+            &TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        )));
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listen = AdminListen::Tcp(vec![closed, listener.local_addr().unwrap()]);
+        tokio::spawn(httpd::serve(listener, move |request| {
+            handle(request, state)
+        }));
+        let (status, _) = tokio::task::spawn_blocking(move || request(&listen, "GET", "/status"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status, 200);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn only_sockets_are_replaced_by_the_admin_socket() {
+        let state = server_state(pool_of(target_of(
+            &TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        )));
+        let path = std::env::temp_dir().join(format!("tow-admin-{}", std::process::id()));
+        std::fs::write(&path, "config").unwrap();
+        assert!(spawn(AdminListen::Unix(path.clone()), state).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "config");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    sync::{atomic::Ordering, Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{Direction, SessionStatus};
+
+static AUDIT_LOG: OnceLock<Mutex<std::fs::File>> = OnceLock::new();
+
+/// one line of the audit log, written when a session ends
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    session: String,
+    peer: Option<SocketAddr>,
+    /// the `X-Forwarded-For` header of the last websocket, as sent by the peer
+    forwarded_for: Option<String>,
+    /// there is no authentication yet, so this is always null
+    identity: Option<String>,
+    target: Option<SocketAddr>,
+    /// the destination asked by the client, instead of the tcp services of the server
+    requested_target: Option<String>,
+    start: String,
+    end: String,
+    duration_ms: u64,
+    bytes_tcp_to_ws: u64,
+    bytes_ws_to_tcp: u64,
+    reconnects: u64,
+    close_reason: &'a str,
+}
+
+/// opens the audit log for appending, sessions that end before this are not recorded
+pub fn init(path: &Path) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let _ = AUDIT_LOG.set(Mutex::new(file));
+    Ok(())
+}
+
+/// writes the record of a session of the server that ended
+pub(crate) fn record(id: u64, status: &SessionStatus, close_reason: &str) {
+    let Some(file) = AUDIT_LOG.get() else {
+        return;
+    };
+    if !matches!(status.dir, Direction::WsToTcp) {
+        return;
+    }
+    let Ok(line) = line(id, status, close_reason, SystemTime::now()) else {
+        return;
+    };
+    if let Err(error) = file.lock().unwrap().write_all(&line) {
+        tracing::error!("erro ao escrever no log de auditoria: {error:?}");
+    }
+}
+
+/// the record of a session that ended at `end`, as a line of json
+fn line(
+    id: u64,
+    status: &SessionStatus,
+    close_reason: &str,
+    end: SystemTime,
+) -> serde_json::Result<Vec<u8>> {
+    let record = AuditRecord {
+        session: format!("{id:016x}"),
+        peer: status.peer(),
+        forwarded_for: status.forwarded_for.lock().unwrap().clone(),
+        identity: None,
+        target: status.target(),
+        requested_target: status.requested_target(),
+        start: rfc3339(status.started),
+        end: rfc3339(end),
+        duration_ms: end
+            .duration_since(status.started)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        bytes_tcp_to_ws: status.bytes_tcp_to_ws.load(Ordering::Relaxed),
+        bytes_ws_to_tcp: status.bytes_ws_to_tcp.load(Ordering::Relaxed),
+        reconnects: status.reconnects.load(Ordering::Relaxed),
+        close_reason,
+    };
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// formats the time in utc, as in `2025-01-31T23:59:59.123Z`
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    // converts days since the epoch to a date in the proleptic gregorian calendar
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{:03}Z",
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn at(millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn times_are_written_in_utc() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(at(951_827_696_789)), "2000-02-29T12:34:56.789Z");
+        assert_eq!(rfc3339(at(1_738_367_999_123)), "2025-01-31T23:59:59.123Z");
+        assert_eq!(rfc3339(at(4_107_542_400_000)), "2100-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn each_record_is_one_line_of_json() {
+        let mut status = SessionStatus::new(Direction::WsToTcp);
+        status.started = at(1_738_367_999_123);
+        *status.peer.lock().unwrap() = Some("10.0.0.7:50000".parse().unwrap());
+        *status.forwarded_for.lock().unwrap() = Some("203.0.113.9, 10.0.0.7".to_owned());
+        status.bytes_tcp_to_ws.store(10, Ordering::Relaxed);
+        status.bytes_ws_to_tcp.store(20, Ordering::Relaxed);
+        status.reconnects.store(2, Ordering::Relaxed);
+        let line = line(0x1234, &status, "idle-timeout", at(1_738_368_001_623)).unwrap();
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert_eq!(line.iter().filter(|&&x| x == b'\n').count(), 1);
+        let record: serde_json::Value = serde_json::from_slice(&line).unwrap();
+        assert_eq!(
+            record,
+            serde_json::json!({
+                "session": "0000000000001234",
+                "peer": "10.0.0.7:50000",
+                "forwarded_for": "203.0.113.9, 10.0.0.7",
+                "identity": null,
+                "target": null,
+                "requested_target": null,
+                "start": "2025-01-31T23:59:59.123Z",
+                "end": "2025-02-01T00:00:01.623Z",
+                "duration_ms": 2500,
+                "bytes_tcp_to_ws": 10,
+                "bytes_ws_to_tcp": 20,
+                "reconnects": 2,
+                "close_reason": "idle-timeout",
+            })
+        );
+    }
+}
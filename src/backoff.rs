@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+pub const DEFAULT_RECONNECT_INITIAL_MS: u64 = 500;
+pub const DEFAULT_RECONNECT_MAX_MS: u64 = 30_000;
+pub const DEFAULT_RECONNECT_MULTIPLIER: f64 = 2.0;
+
+/// how long the client waits between failed attempts to connect the websocket
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// the wait after the first failed attempt
+    pub initial: Duration,
+    /// the wait never grows past this
+    pub max: Duration,
+    /// how much the wait grows after each failed attempt
+    pub multiplier: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(DEFAULT_RECONNECT_INITIAL_MS),
+            max: Duration::from_millis(DEFAULT_RECONNECT_MAX_MS),
+            multiplier: DEFAULT_RECONNECT_MULTIPLIER,
+        }
+    }
+}
+
+impl Backoff {
+    /// the wait after the nth failed attempt in a row, starting at 1
+    /// a random half of the wait is dropped, so clients that lost the server together do not come back together
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(64) as i32;
+        let base = self
+            .initial
+            .mul_f64(self.multiplier.max(1.0).powi(exponent).min(u32::MAX as f64))
+            .min(self.max);
+        base.mul_f64(rand::random_range(0.5..=1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKOFF: Backoff = Backoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_millis(1000),
+        multiplier: 2.0,
+    };
+
+    /// asserts every delay after the failures is between half the wait and the wait
+    fn assert_jittered(backoff: &Backoff, failures: u32, wait: u64) {
+        for _ in 0..100 {
+            let delay = backoff.delay(failures);
+            assert!(
+                delay >= Duration::from_millis(wait) / 2 && delay <= Duration::from_millis(wait),
+                "{failures} failures waited {delay:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_wait_grows_until_the_max() {
+        for (failures, wait) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (6, 1000)] {
+            assert_jittered(&BACKOFF, failures, wait);
+        }
+        assert_jittered(&BACKOFF, u32::MAX, 1000);
+    }
+
+    #[test]
+    fn the_jitter_drops_up_to_half_the_wait() {
+        let delays = (0..1000).map(|_| BACKOFF.delay(1)).collect::<Vec<_>>();
+        assert!(delays.iter().all(|x| (50..=100).contains(&x.as_millis())));
+        assert!(delays.iter().any(|x| x.as_millis() < 60));
+        assert!(delays.iter().any(|x| x.as_millis() > 90));
+    }
+
+    #[test]
+    fn the_wait_starts_over_after_a_success() {
+        assert_jittered(&BACKOFF, 10, 1000);
+        // the caller resets the count of failures once connected
+        assert_jittered(&BACKOFF, 1, 100);
+        assert_jittered(&BACKOFF, 0, 100);
+    }
+
+    #[test]
+    fn the_wait_never_shrinks() {
+        let backoff = Backoff {
+            multiplier: 0.5,
+            ..BACKOFF
+        };
+        assert_jittered(&backoff, 5, 100);
+    }
+}
@@ -1,4 +1,11 @@
 use clap::{Parser, Subcommand};
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use tcp_over_ws::{
+    admin::{AdminListen, SessionInfo, Status},
+    proxy::{ProxyOptions, ProxySource},
+    shaping::Shaping,
+    ClientOptions,
+};
 
 /// Prático Web
 #[derive(Parser)]
@@ -21,8 +28,40 @@ enum Commands {
     Stop,
     /// Restart the service
     Restart,
-    /// Get the service status
+    /// Get the service status, and the statistics of the running instance
     Status,
+    /// List or kill the sessions of the running instance
+    Sessions {
+        #[command(subcommand)]
+        command: Option<SessionsCommand>,
+    },
+    /// Connect stdin and stdout to the tcp service of a websocket server, as ssh ProxyCommand
+    Connect {
+        /// The websocket server, as ws://host:port/path or wss://host/path
+        remote: String,
+        /// How long the server keeps the session while the websocket is reconnecting, in milliseconds
+        #[arg(long, default_value_t = tcp_over_ws::DEFAULT_TIMEOUT_MS)]
+        timeout_ms: u64,
+        /// The proxy to connect through, "direct" to not use one, or empty to use HTTPS_PROXY, HTTP_PROXY and ALL_PROXY
+        #[arg(long, default_value = "")]
+        proxy: String,
+        /// The hosts connected without proxy, as in NO_PROXY
+        #[arg(long, default_value = "")]
+        no_proxy: String,
+        /// The log level, the log is written to stderr
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionsCommand {
+    /// List the sessions
+    List,
+    /// Kill a session, the id is shown by the list command
+    Kill {
+        id: String,
+    },
 }
 
 pub fn cli() {
@@ -32,6 +71,10 @@ pub fn cli() {
     else {
         return;
     };
+    // the log of the service is configured by config.toml, the commands only write to the terminal
+    if !matches!(command, Commands::Connect { .. }) {
+        crate::log::init_stderr("info");
+    }
     let result = match command {
         Commands::Install => {
             serviceator::management::install().map(|install| {
@@ -79,7 +122,8 @@ pub fn cli() {
             })
         },
         Commands::Status => {
-            serviceator::management::status().map(|status| {
+            // the instance may be running outside of the service manager
+            let result = serviceator::management::status().map(|status| {
                 let status = match status {
                     serviceator::ServiceStatus::Stopped => "parado",
                     serviceator::ServiceStatus::Starting => "iniciando",
@@ -90,7 +134,41 @@ pub fn cli() {
                     serviceator::ServiceStatus::Paused => "pausado",
                 };
                 println!("serviço está {status}");
-            })
+            });
+            if let Err(error) = &result {
+                println!("erro ao consultar o serviço: {error:?}");
+            }
+            instance_status();
+            if result.is_err() {
+                std::process::exit(1)
+            }
+            Ok(())
+        },
+        Commands::Connect {
+            remote,
+            timeout_ms,
+            proxy,
+            no_proxy,
+            log_level,
+        } => {
+            // stdout carries the session, nothing else can be printed there
+            crate::log::init_stderr(&log_level);
+            let code = match connect(&remote, timeout_ms, &proxy, no_proxy) {
+                Ok(()) => 0,
+                Err(error) => {
+                    eprintln!("erro: {error}");
+                    1
+                }
+            };
+            // the thread reading stdin may still be blocked, exiting does not wait for it
+            std::process::exit(code)
+        },
+        Commands::Sessions { command } => {
+            match command.unwrap_or(SessionsCommand::List) {
+                SessionsCommand::List => list_sessions(),
+                SessionsCommand::Kill { id } => kill_session(&id),
+            }
+            Ok(())
         },
     };
     match result {
@@ -103,3 +181,238 @@ pub fn cli() {
         },
     }
 }
+
+fn connect(remote: &str, timeout_ms: u64, proxy: &str, no_proxy: String) -> Result<(), String> {
+    let connect_request = remote
+        .into_client_request()
+        .map_err(|error| format!("o endereço do servidor {remote:?} não é válido: {error}"))?;
+    let options = ClientOptions {
+        timeout: timeout_ms,
+        proxy: ProxyOptions {
+            source: proxy.parse::<ProxySource>()?,
+            no_proxy,
+        },
+        ..ClientOptions::default()
+    };
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|error| error.to_string())?;
+    rt.block_on(tcp_over_ws::stdio_to_ws_service(connect_request, options))
+        .map_err(|error| error.to_string())
+}
+
+fn admin_listen() -> AdminListen {
+    match crate::config::load_admin_listen() {
+        Some(admin) => admin,
+        None => {
+            println!("a api de admin não está configurada, defina admin no config.toml");
+            std::process::exit(1)
+        },
+    }
+}
+
+/// makes a request to the running instance, exits if it fails
+fn admin_request<T: serde::de::DeserializeOwned>(admin: &AdminListen, method: &str, path: &str) -> T {
+    match tcp_over_ws::admin::request(admin, method, path) {
+        Ok((200, body)) => match serde_json::from_slice(&body) {
+            Ok(value) => value,
+            Err(error) => {
+                println!("erro: resposta inválida da api de admin: {error}");
+                std::process::exit(1)
+            },
+        },
+        Ok((404, _)) => {
+            println!("não encontrado");
+            std::process::exit(1)
+        },
+        Ok((status, body)) => {
+            println!("erro: a api de admin respondeu {status}: {}", String::from_utf8_lossy(&body));
+            std::process::exit(1)
+        },
+        Err(error) => {
+            println!("erro ao conectar na api de admin, o serviço está executando? {error}");
+            std::process::exit(1)
+        },
+    }
+}
+
+/// shows the statistics of the running instance, the throughput is measured over one second
+fn instance_status() {
+    let Some(admin) = crate::config::load_admin_listen() else {
+        println!("defina admin no config.toml para ver as estatísticas do serviço");
+        return;
+    };
+    let before = admin_request::<Status>(&admin, "GET", "/status");
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    let status = admin_request::<Status>(&admin, "GET", "/status");
+    match status.listen {
+        Some(listen) => println!("escutando em {listen}"),
+        None => println!("escutando em endereço desconhecido"),
+    }
+    if status.draining {
+        println!("não está aceitando novas sessões");
+    }
+    println!("executando há {}", format_duration(status.uptime_secs));
+    println!("sessões: {} ({} com websocket)", status.sessions, status.attached_sessions);
+    print_traffic(
+        "",
+        (status.bytes_tcp_to_ws, before.bytes_tcp_to_ws),
+        (status.bytes_ws_to_tcp, before.bytes_ws_to_tcp),
+        &status.shaping,
+    );
+    if let Some(limits) = format_shaping(&status.session_shaping) {
+        println!("limites de cada sessão: {limits}");
+    }
+    println!("serviços tcp:");
+    for backend in &status.backends {
+        println!(
+            "  {} {}, {} sessões",
+            backend.target,
+            if backend.up { "no ar" } else { "fora do ar" },
+            backend.active_sessions,
+        );
+    }
+    if !status.tunnels.is_empty() {
+        println!("túneis:");
+    }
+    for (index, tunnel) in status.tunnels.iter().enumerate() {
+        let listen = tunnel.listen.as_deref().unwrap_or("-");
+        println!("  {listen} -> {}, {} sessões", tunnel.remote, tunnel.sessions);
+        let (before_tcp_to_ws, before_ws_to_tcp) = before
+            .tunnels
+            .get(index)
+            .map(|x| (x.bytes_tcp_to_ws, x.bytes_ws_to_tcp))
+            .unwrap_or_default();
+        print_traffic(
+            "    ",
+            (tunnel.bytes_tcp_to_ws, before_tcp_to_ws),
+            (tunnel.bytes_ws_to_tcp, before_ws_to_tcp),
+            &tunnel.shaping,
+        );
+        if let Some(limits) = format_shaping(&tunnel.session_shaping) {
+            println!("    limites de cada sessão: {limits}");
+        }
+        if tunnel.reconnects > 0 || tunnel.failed_connects > 0 {
+            println!(
+                "    {} reconecções, {} tentativas de conecção falhas",
+                tunnel.reconnects, tunnel.failed_connects
+            );
+        }
+    }
+    if status.recent_errors.is_empty() {
+        println!("nenhum erro recente");
+    } else {
+        println!("erros recentes:");
+        for error in &status.recent_errors {
+            let session = error.session.as_deref().map(|x| format!(" [{x}]")).unwrap_or_default();
+            println!(
+                "  há {} {}{session}: {}",
+                format_duration(error.age_secs),
+                error.level,
+                error.message,
+            );
+        }
+    }
+}
+
+fn list_sessions() {
+    let admin = admin_listen();
+    let sessions = admin_request::<Vec<SessionInfo>>(&admin, "GET", "/sessions");
+    if sessions.is_empty() {
+        println!("nenhuma sessão");
+        return;
+    }
+    println!(
+        "{:<16}  {:<21}  {:<21}  {:>10}  {:<9}  {:>8}",
+        "id", "cliente", "serviço", "buffer", "websocket", "ociosa"
+    );
+    for session in &sessions {
+        let address = |x: Option<std::net::SocketAddr>| x.map(|x| x.to_string()).unwrap_or("-".into());
+        println!(
+            "{:<16}  {:<21}  {:<21}  {:>10}  {:<9}  {:>8}",
+            session.id,
+            address(session.peer),
+            address(session.target),
+            format_bytes(session.buffered),
+            if session.closed {
+                "encerrada"
+            } else if session.attached {
+                "sim"
+            } else {
+                "não"
+            },
+            format_duration(session.idle_ms / 1000),
+        );
+    }
+}
+
+fn kill_session(id: &str) {
+    let admin = admin_listen();
+    let session = admin_request::<SessionInfo>(&admin, "DELETE", &format!("/sessions/{id}"));
+    println!("sessão {} encerrada", session.id);
+}
+
+/// prints the bytes in each direction, the rate in the last second and the limits
+/// each direction is given as the bytes now and the bytes one second before
+fn print_traffic(indent: &str, (tcp_to_ws, before_tcp_to_ws): (u64, u64), (ws_to_tcp, before_ws_to_tcp): (u64, u64), shaping: &Shaping) {
+    let limit = |limit: u64| {
+        if limit > 0 {
+            format!(", limite {}/s", format_bytes(limit))
+        } else {
+            String::new()
+        }
+    };
+    println!(
+        "{indent}tráfego tcp -> ws: {} ({}/s{})",
+        format_bytes(tcp_to_ws),
+        format_bytes(tcp_to_ws.saturating_sub(before_tcp_to_ws)),
+        limit(shaping.tcp_to_ws),
+    );
+    println!(
+        "{indent}tráfego ws -> tcp: {} ({}/s{})",
+        format_bytes(ws_to_tcp),
+        format_bytes(ws_to_tcp.saturating_sub(before_ws_to_tcp)),
+        limit(shaping.ws_to_tcp),
+    );
+    if shaping.total > 0 {
+        println!("{indent}limite das duas direções: {}/s", format_bytes(shaping.total));
+    }
+}
+
+/// the limits that are set, `None` if there are none
+fn format_shaping(shaping: &Shaping) -> Option<String> {
+    let limits = [
+        ("tcp -> ws", shaping.tcp_to_ws),
+        ("ws -> tcp", shaping.ws_to_tcp),
+        ("total", shaping.total),
+    ]
+    .into_iter()
+    .filter(|(_, limit)| *limit > 0)
+    .map(|(name, limit)| format!("{name} {}/s", format_bytes(limit)))
+    .collect::<Vec<_>>();
+    (!limits.is_empty()).then(|| limits.join(", "))
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
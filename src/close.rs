@@ -0,0 +1,170 @@
+use std::io::ErrorKind;
+
+use async_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tow_protocol::close_code;
+
+/// why a session was closed, sent to the other side in the close frame of the websocket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// the tcp service refused the connection
+    BackendRefused,
+    /// the connection to the tcp service did not complete in time
+    BackendTimeout,
+    /// the tcp service could not be resolved or reached
+    BackendUnreachable,
+    /// the session was killed through the admin api
+    Killed,
+    /// no bytes went through the session for longer than the idle timeout
+    IdleTimeout,
+    /// the session was open for longer than the maximum lifetime
+    MaxLifetime,
+    /// the destination asked by the client is not allowed by the target policy
+    TargetDenied,
+    /// the server no longer had the session the client was resuming, the bytes in flight were lost
+    SessionLost,
+}
+
+impl CloseReason {
+    /// the close code sent in the close frame, in the range reserved for applications
+    pub fn code(self) -> u16 {
+        match self {
+            CloseReason::BackendRefused => close_code::BACKEND_REFUSED,
+            CloseReason::BackendTimeout => close_code::BACKEND_TIMEOUT,
+            CloseReason::BackendUnreachable => close_code::BACKEND_UNREACHABLE,
+            CloseReason::Killed => close_code::KILLED,
+            CloseReason::IdleTimeout => close_code::IDLE_TIMEOUT,
+            CloseReason::MaxLifetime => close_code::MAX_LIFETIME,
+            CloseReason::TargetDenied => close_code::TARGET_DENIED,
+            CloseReason::SessionLost => close_code::SESSION_LOST,
+        }
+    }
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            close_code::BACKEND_REFUSED => Some(CloseReason::BackendRefused),
+            close_code::BACKEND_TIMEOUT => Some(CloseReason::BackendTimeout),
+            close_code::BACKEND_UNREACHABLE => Some(CloseReason::BackendUnreachable),
+            close_code::KILLED => Some(CloseReason::Killed),
+            close_code::IDLE_TIMEOUT => Some(CloseReason::IdleTimeout),
+            close_code::MAX_LIFETIME => Some(CloseReason::MaxLifetime),
+            close_code::TARGET_DENIED => Some(CloseReason::TargetDenied),
+            close_code::SESSION_LOST => Some(CloseReason::SessionLost),
+            _ => None,
+        }
+    }
+    /// the reason text sent in the close frame
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::BackendRefused => "backend-refused",
+            CloseReason::BackendTimeout => "backend-timeout",
+            CloseReason::BackendUnreachable => "backend-unreachable",
+            CloseReason::Killed => "killed",
+            CloseReason::IdleTimeout => "idle-timeout",
+            CloseReason::MaxLifetime => "max-lifetime",
+            CloseReason::TargetDenied => "target-denied",
+            CloseReason::SessionLost => "session-lost",
+        }
+    }
+    pub fn from_connect_error(error: &std::io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::ConnectionRefused => CloseReason::BackendRefused,
+            ErrorKind::TimedOut => CloseReason::BackendTimeout,
+            _ => CloseReason::BackendUnreachable,
+        }
+    }
+    /// if the local tcp connection should be reset instead of closed normally, so the program using it sees an error
+    pub fn is_error(self) -> bool {
+        match self {
+            CloseReason::BackendRefused
+            | CloseReason::BackendTimeout
+            | CloseReason::BackendUnreachable
+            | CloseReason::Killed
+            | CloseReason::TargetDenied
+            | CloseReason::SessionLost => true,
+            CloseReason::IdleTimeout | CloseReason::MaxLifetime => false,
+        }
+    }
+    pub fn frame(self) -> CloseFrame {
+        CloseFrame {
+            code: CloseCode::from(self.code()),
+            reason: self.as_str().into(),
+        }
+    }
+}
+
+impl std::fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloseReason::BackendRefused => f.write_str("o serviço tcp recusou a conecção"),
+            CloseReason::BackendTimeout => f.write_str("o serviço tcp não respondeu a tempo"),
+            CloseReason::BackendUnreachable => f.write_str("o serviço tcp não está acessível"),
+            CloseReason::Killed => f.write_str("a sessão foi encerrada pelo admin"),
+            CloseReason::IdleTimeout => f.write_str("a sessão ficou ociosa por tempo demais"),
+            CloseReason::MaxLifetime => f.write_str("a sessão atingiu a duração máxima"),
+            CloseReason::TargetDenied => f.write_str("o destino pedido não é permitido"),
+            CloseReason::SessionLost => f.write_str("o servidor perdeu a sessão"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [CloseReason; 8] = [
+        CloseReason::BackendRefused,
+        CloseReason::BackendTimeout,
+        CloseReason::BackendUnreachable,
+        CloseReason::Killed,
+        CloseReason::IdleTimeout,
+        CloseReason::MaxLifetime,
+        CloseReason::TargetDenied,
+        CloseReason::SessionLost,
+    ];
+
+    #[test]
+    fn codes_are_read_back_as_their_reason() {
+        for reason in ALL {
+            assert!((4000..5000).contains(&reason.code()), "{reason:?}");
+            assert_eq!(CloseReason::from_code(reason.code()), Some(reason));
+        }
+        for code in [1000, 1006, 4000, 4999] {
+            assert_eq!(CloseReason::from_code(code), None);
+        }
+    }
+
+    #[test]
+    fn codes_and_texts_are_unique() {
+        for (index, reason) in ALL.iter().enumerate() {
+            for other in &ALL[index + 1..] {
+                assert_ne!(reason.code(), other.code());
+                assert_ne!(reason.as_str(), other.as_str());
+            }
+        }
+    }
+
+    #[test]
+    fn the_frame_carries_the_code_and_the_text() {
+        let frame = CloseReason::BackendTimeout.frame();
+        assert_eq!(u16::from(frame.code), 4002);
+        assert_eq!(frame.reason.as_str(), "backend-timeout");
+    }
+
+    #[test]
+    fn connect_errors_are_told_apart() {
+        let reason = |kind: ErrorKind| CloseReason::from_connect_error(&kind.into());
+        assert_eq!(
+            reason(ErrorKind::ConnectionRefused),
+            CloseReason::BackendRefused
+        );
+        assert_eq!(reason(ErrorKind::TimedOut), CloseReason::BackendTimeout);
+        assert_eq!(reason(ErrorKind::NotFound), CloseReason::BackendUnreachable);
+    }
+
+    #[test]
+    fn expired_sessions_are_not_errors() {
+        for reason in ALL {
+            let expired = matches!(reason, CloseReason::IdleTimeout | CloseReason::MaxLifetime);
+            assert_eq!(reason.is_error(), !expired, "{reason:?}");
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::{io::ErrorKind, net::SocketAddr, time::Duration};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+
+use crate::{
+    addr::{Endpoint, Target},
+    metrics,
+    stream::Stream,
+};
+
+/// how long to wait for a connection attempt before starting the next one in parallel (RFC 8305)
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// tries the endpoints in order, returning the first connection that succeeds and its endpoint
+pub async fn connect_any(
+    endpoints: &[Endpoint],
+    timeout: Duration,
+) -> std::io::Result<(Stream, &Endpoint)> {
+    let mut last_error = None;
+    for endpoint in endpoints {
+        match connect_endpoint(endpoint, timeout).await {
+            Ok(stream) => return Ok((stream, endpoint)),
+            Err(error) => {
+                tracing::warn!("erro ao conectar em {endpoint}: {error:?}");
+                metrics::add(&metrics::BACKEND_CONNECT_FAILURES, 1);
+                last_error = Some(error);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "nenhum endereço de conecção configurado",
+        )
+    }))
+}
+
+/// connects to a target as `connect_target`, or to a unix socket
+pub async fn connect_endpoint(endpoint: &Endpoint, timeout: Duration) -> std::io::Result<Stream> {
+    match endpoint {
+        Endpoint::Tcp(target) => connect_target(target, timeout).await.map(Stream::Tcp),
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            tokio::time::timeout(timeout, tokio::net::UnixStream::connect(path))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(std::io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("tempo esgotado ao conectar em {endpoint}"),
+                    ))
+                })
+                .map(Stream::Unix)
+        }
+    }
+}
+
+/// resolves the target and connects to it, trying every resolved address happy eyeballs style
+///
+/// fails with `ErrorKind::TimedOut` if no address connects within `timeout`
+pub async fn connect_target(
+    target: &Target,
+    timeout: Duration,
+) -> std::io::Result<tokio::net::TcpStream> {
+    tokio::time::timeout(timeout, connect_target_inner(target))
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("tempo esgotado ao conectar em {target}"),
+            ))
+        })
+}
+
+async fn connect_target_inner(target: &Target) -> std::io::Result<tokio::net::TcpStream> {
+    let addrs = tokio::net::lookup_host((target.host.as_str(), target.port)).await?;
+    connect_addrs_inner(target, addrs.collect()).await
+}
+
+/// connects to the target trying only the addresses given, already resolved, happy eyeballs style
+pub async fn connect_addrs(
+    target: &Target,
+    addrs: Vec<SocketAddr>,
+    timeout: Duration,
+) -> std::io::Result<tokio::net::TcpStream> {
+    tokio::time::timeout(timeout, connect_addrs_inner(target, addrs))
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("tempo esgotado ao conectar em {target}"),
+            ))
+        })
+}
+
+async fn connect_addrs_inner(
+    target: &Target,
+    addrs: Vec<SocketAddr>,
+) -> std::io::Result<tokio::net::TcpStream> {
+    let mut addrs = interleave_families(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+    loop {
+        if let Some(addr) = addrs.next() {
+            attempts.push(tokio::net::TcpStream::connect(addr));
+        }
+        if attempts.is_empty() {
+            return Err(last_error.unwrap_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("nenhum endereço encontrado para {target}"),
+                )
+            }));
+        }
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                Err(error) => last_error = Some(error),
+            },
+            () = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY) => {}
+        }
+    }
+}
+
+/// orders the addresses alternating between ipv6 and ipv4, starting with the family of the first address
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_is_ipv6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .iter()
+        .copied()
+        .partition(|x| x.is_ipv6() == first_is_ipv6);
+    let mut result = Vec::with_capacity(addrs.len());
+    preferred.reverse();
+    other.reverse();
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => return result,
+            (a, b) => result.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn addr(text: &str) -> SocketAddr {
+        text.parse().unwrap()
+    }
+
+    fn endpoint(addr: SocketAddr) -> Endpoint {
+        Endpoint::Tcp(Target {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+        })
+    }
+
+    /// an address nothing listens on
+    async fn closed_port() -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn families_alternate_starting_with_the_first() {
+        let addrs = ["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"].map(addr);
+        assert_eq!(
+            interleave_families(addrs.to_vec()),
+            ["[::1]:1", "10.0.0.1:1", "[::2]:1", "10.0.0.2:1", "[::3]:1"].map(addr)
+        );
+        let addrs = ["10.0.0.1:1", "[::1]:1", "10.0.0.2:1"].map(addr);
+        assert_eq!(
+            interleave_families(addrs.to_vec()),
+            ["10.0.0.1:1", "[::1]:1", "10.0.0.2:1"].map(addr)
+        );
+        assert!(interleave_families(Vec::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn fallbacks_are_tried_in_order() {
+        let first = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoints = [
+            endpoint(closed_port().await),
+            endpoint(first.local_addr().unwrap()),
+            endpoint(second.local_addr().unwrap()),
+        ];
+        let (stream, endpoint) = connect_any(&endpoints, TIMEOUT).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), first.local_addr().unwrap());
+        assert_eq!(endpoint, &endpoints[1]);
+    }
+
+    #[tokio::test]
+    async fn the_last_error_is_returned_when_every_target_fails() {
+        let endpoints = [endpoint(closed_port().await), endpoint(closed_port().await)];
+        let error = connect_any(&endpoints, TIMEOUT).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+        let error = connect_any(&[], TIMEOUT).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
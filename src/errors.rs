@@ -0,0 +1,134 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// how many errors are kept, older ones are discarded
+const MAX_RECENT_ERRORS: usize = 32;
+
+static RECENT_ERRORS: Mutex<VecDeque<RecentError>> = Mutex::new(VecDeque::new());
+
+/// a warning or error that was logged, kept to be shown by the status command
+#[derive(Debug, Clone)]
+pub struct RecentError {
+    pub time: Instant,
+    pub level: Level,
+    /// the id of the session the error happened in, if any
+    pub session: Option<String>,
+    pub message: String,
+}
+impl RecentError {
+    /// how long ago the error happened
+    pub fn age(&self) -> Duration {
+        self.time.elapsed()
+    }
+}
+
+/// the most recent errors, the newest first
+pub fn recent_errors() -> Vec<RecentError> {
+    RECENT_ERRORS
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .cloned()
+        .collect()
+}
+
+/// a layer that keeps the last warnings and errors logged, to be added to the global logger
+pub fn layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    RecentErrorsLayer
+}
+
+struct RecentErrorsLayer;
+
+/// the id field of a session span, stored in the extensions of the span
+struct SessionId(String);
+
+impl<S> Layer<S> for RecentErrorsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::new("id");
+        attrs.record(&mut visitor);
+        if let (Some(value), Some(span)) = (visitor.value, ctx.span(id)) {
+            span.extensions_mut().replace(SessionId(value));
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::new("id");
+        values.record(&mut visitor);
+        if let (Some(value), Some(span)) = (visitor.value, ctx.span(id)) {
+            span.extensions_mut().replace(SessionId(value));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level > Level::WARN {
+            return;
+        }
+        let mut visitor = FieldVisitor::new("message");
+        event.record(&mut visitor);
+        let session = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .from_root()
+                .find_map(|span| span.extensions().get::<SessionId>().map(|x| x.0.clone()))
+        });
+        let mut recent = RECENT_ERRORS.lock().unwrap();
+        if recent.len() >= MAX_RECENT_ERRORS {
+            recent.pop_front();
+        }
+        recent.push_back(RecentError {
+            time: Instant::now(),
+            level,
+            session,
+            message: visitor.value.unwrap_or_default() + &visitor.rest,
+        });
+    }
+}
+
+/// extracts a single field, the other fields are formatted into `rest`
+struct FieldVisitor {
+    name: &'static str,
+    value: Option<String>,
+    rest: String,
+}
+impl FieldVisitor {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            value: None,
+            rest: String::new(),
+        }
+    }
+}
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == self.name {
+            self.value = Some(value.to_owned());
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == self.name {
+            self.value = Some(format!("{value:?}"));
+        } else {
+            let _ = write!(self.rest, " {}={value:?}", field.name());
+        }
+    }
+}
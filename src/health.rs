@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    connect,
+    pool::{Backend, Pool},
+    proxy_protocol,
+};
+
+pub const DEFAULT_HEALTH_TIMEOUT_MS: u64 = 3_000;
+
+/// periodically connects to every backend of a pool to find out if they are up before a session needs them
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// bytes written after connecting, nothing is written if empty
+    pub send: Vec<u8>,
+    /// bytes that must be received after connecting for the backend to be considered up, nothing is read if empty
+    pub expect: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    /// no check was done yet, or checks are disabled
+    Unknown,
+    Up,
+    Down,
+}
+impl std::fmt::Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Health::Unknown => f.write_str("desconhecido"),
+            Health::Up => f.write_str("no ar"),
+            Health::Down => f.write_str("fora do ar"),
+        }
+    }
+}
+
+/// spawns one task per backend running the health checks of the pool, if they are enabled
+pub fn spawn_health_checks(pool: &'static Pool) {
+    let Some(check) = &pool.health_check else {
+        return;
+    };
+    for backend in &pool.backends {
+        tokio::spawn(async move {
+            loop {
+                let health = match tokio::time::timeout(
+                    check.timeout,
+                    run_check(check, backend, pool.proxy_protocol(&backend.target)),
+                )
+                .await
+                {
+                    Ok(Ok(())) => Health::Up,
+                    Ok(Err(error)) => {
+                        if backend.health() != Health::Down {
+                            tracing::warn!("verificação de {} falhou: {error:?}", backend.target);
+                        }
+                        Health::Down
+                    }
+                    Err(_) => {
+                        if backend.health() != Health::Down {
+                            tracing::warn!("verificação de {} falhou (timeout)", backend.target);
+                        }
+                        Health::Down
+                    }
+                };
+                let previous = backend.set_health(health);
+                if previous != health {
+                    tracing::info!("{} está {health}", backend.target);
+                }
+                tokio::time::sleep(check.interval).await;
+            }
+        });
+    }
+}
+
+async fn run_check(
+    check: &HealthCheck,
+    backend: &Backend,
+    proxy_protocol: Option<proxy_protocol::Version>,
+) -> std::io::Result<()> {
+    let mut stream = connect::connect_endpoint(&backend.target, check.timeout).await?;
+    // the check is a connection of the proxy itself, with no client to announce
+    if let Some(version) = proxy_protocol {
+        stream
+            .write_all(&proxy_protocol::header(version, None))
+            .await?;
+    }
+    if !check.send.is_empty() {
+        stream.write_all(&check.send).await?;
+    }
+    if !check.expect.is_empty() {
+        let mut received = Vec::new();
+        let mut buffer = [0; 1024];
+        while !received
+            .windows(check.expect.len())
+            .any(|x| x == check.expect)
+        {
+            let bytes_read = stream.read(&mut buffer).await?;
+            if bytes_read == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "a conecção foi encerrada antes de receber a resposta esperada",
+                ));
+            }
+            received.extend_from_slice(&buffer[..bytes_read]);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::addr::{Endpoint, Target};
+
+    fn check(send: &[u8], expect: &[u8]) -> HealthCheck {
+        HealthCheck {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_millis(DEFAULT_HEALTH_TIMEOUT_MS),
+            send: send.to_vec(),
+            expect: expect.to_vec(),
+        }
+    }
+
+    fn backend_of(listener: &TcpListener) -> Backend {
+        let addr = listener.local_addr().unwrap();
+        Backend::new(
+            Endpoint::Tcp(Target {
+                host: addr.ip().to_string(),
+                port: addr.port(),
+            }),
+            1,
+        )
+    }
+
+    /// answers the first connection with `reply` after reading `request`, then closes it
+    async fn answer(listener: TcpListener, request: &'static [u8], reply: &'static [u8]) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = vec![0; request.len()];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, request);
+        stream.write_all(reply).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_backend_accepting_connections_is_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = backend_of(&listener);
+        run_check(&check(b"", b""), &backend, None).await.unwrap();
+        drop(listener);
+        assert!(run_check(&check(b"", b""), &backend, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn the_expected_reply_must_be_received() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = backend_of(&listener);
+        tokio::spawn(answer(listener, b"PING\r\n", b"+PONG\r\n"));
+        run_check(&check(b"PING\r\n", b"PONG"), &backend, None)
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = backend_of(&listener);
+        tokio::spawn(answer(listener, b"PING\r\n", b"-ERR\r\n"));
+        let error = run_check(&check(b"PING\r\n", b"PONG"), &backend, None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn checks_decide_if_the_backend_is_up() {
+        let pool = Pool::new(
+            Default::default(),
+            vec![Backend::new(
+                Endpoint::Tcp(Target {
+                    host: "127.0.0.1".to_owned(),
+                    port: 1,
+                }),
+                1,
+            )],
+            Vec::new(),
+        );
+        let backend = &pool.backends[0];
+        assert_eq!(backend.health(), Health::Unknown);
+        assert!(pool.is_available());
+        assert_eq!(backend.set_health(Health::Down), Health::Unknown);
+        assert!(!backend.is_healthy());
+        assert!(!pool.is_available());
+        assert_eq!(backend.set_health(Health::Up), Health::Down);
+        assert!(pool.is_available());
+    }
+}
@@ -0,0 +1,455 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::TcpListener,
+};
+
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Request {
+    pub method: String,
+    /// the path without the query string
+    pub path: String,
+    pub query: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, x)| x.as_str())
+    }
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|x| x.split_once('='))
+            .find(|(x, _)| *x == name)
+            .map(|(_, x)| x)
+    }
+    /// if the request asks to upgrade the connection to a websocket
+    pub fn is_websocket(&self) -> bool {
+        self.header("upgrade")
+            .is_some_and(|x| x.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+    pub fn text(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body)
+    }
+    pub fn not_found() -> Self {
+        Self::text(404, "not found\n")
+    }
+    pub fn method_not_allowed() -> Self {
+        Self::text(405, "method not allowed\n")
+    }
+    pub fn json(status: u16, value: &impl serde::Serialize) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self::new(status, "application/json", body),
+            Err(error) => Self::text(500, format!("{error}\n")),
+        }
+    }
+}
+
+/// a minimal http/1.1 server, accepts connections forever answering a single request on each with `handler`
+pub async fn serve<F, Fut>(listener: TcpListener, handler: F) -> std::convert::Infallible
+where
+    F: Fn(Request) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, handler.clone()));
+            }
+            Err(error) => {
+                tracing::warn!("erro ao tentar aceitar conecção http: {error:?}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// the same as `serve`, but on a unix socket
+#[cfg(unix)]
+pub async fn serve_unix<F, Fut>(
+    listener: tokio::net::UnixListener,
+    handler: F,
+) -> std::convert::Infallible
+where
+    F: Fn(Request) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, handler.clone()));
+            }
+            Err(error) => {
+                tracing::warn!("erro ao tentar aceitar conecção http: {error:?}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// answers a single request on the connection with `handler`
+pub async fn handle_connection<S, F, Fut>(mut stream: S, handler: F)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => request,
+        Ok(Err(error)) => {
+            tracing::debug!("requisição http inválida: {error:?}");
+            let _ = write_response(&mut stream, &Response::text(400, "bad request\n")).await;
+            return;
+        }
+        Err(_) => return,
+    };
+    let response = handler(request).await;
+    let _ = write_response(&mut stream, &response).await;
+}
+
+pub async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<Request> {
+    let mut buffer = Vec::with_capacity(1024);
+    let head_len = read_head(stream, &mut buffer).await?;
+    let mut request = parse_head(&buffer[..head_len])?;
+    request.body = buffer[head_len..].to_vec();
+    let content_length = request
+        .header("content-length")
+        .map(|x| {
+            x.parse::<usize>()
+                .map_err(|_| invalid("content-length inválido"))
+        })
+        .transpose()?
+        .unwrap_or(0);
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("corpo muito grande"));
+    }
+    while request.body.len() < content_length {
+        let mut chunk = [0; 4096];
+        let bytes_read = stream.read(&mut chunk).await?;
+        if bytes_read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        request.body.extend_from_slice(&chunk[..bytes_read]);
+    }
+    request.body.truncate(content_length);
+    Ok(request)
+}
+
+/// reads the head of a request, without its body, keeping every byte read to be read again from the returned stream
+/// so the connection can be handed to `handle_connection` or to another protocol, as websockets, after looking at the request
+pub async fn peek_request<S: AsyncRead + Unpin>(
+    mut stream: S,
+) -> (Prefixed<S>, std::io::Result<Request>) {
+    let mut buffer = Vec::with_capacity(1024);
+    let result =
+        match tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream, &mut buffer)).await {
+            Ok(Ok(head_len)) => parse_head(&buffer[..head_len]),
+            Ok(Err(error)) => Err(error),
+            Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+        };
+    let stream = Prefixed {
+        prefix: buffer,
+        position: 0,
+        inner: stream,
+    };
+    (stream, result)
+}
+
+/// reads into `buffer` until the end of the head, returning its length, more may have been read after it
+async fn read_head<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    buffer: &mut Vec<u8>,
+) -> std::io::Result<usize> {
+    loop {
+        if let Some(index) = buffer.windows(4).position(|x| x == b"\r\n\r\n") {
+            return Ok(index + 4);
+        }
+        if buffer.len() > MAX_HEAD_SIZE {
+            return Err(invalid("cabeçalho muito grande"));
+        }
+        let mut chunk = [0; 1024];
+        let bytes_read = stream.read(&mut chunk).await?;
+        if bytes_read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buffer.extend_from_slice(&chunk[..bytes_read]);
+    }
+}
+
+/// parses the head of a request, the body is left empty
+fn parse_head(head: &[u8]) -> std::io::Result<Request> {
+    let head = std::str::from_utf8(head).map_err(|_| invalid("cabeçalho não é utf8"))?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().unwrap_or("").to_owned();
+    let target = request_line
+        .next()
+        .ok_or_else(|| invalid("linha de requisição inválida"))?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers = lines
+        .filter(|x| !x.is_empty())
+        .filter_map(|x| x.split_once(':'))
+        .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+        .collect::<Vec<_>>();
+    Ok(Request {
+        method,
+        path: path.to_owned(),
+        query: query.to_owned(),
+        headers,
+        body: Vec::new(),
+    })
+}
+
+/// a stream that returns the bytes of `prefix` before the ones of the inner stream, see `peek_request`
+pub struct Prefixed<S> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: S,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Prefixed<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.position < this.prefix.len() {
+            let len = buf.remaining().min(this.prefix.len() - this.position);
+            buf.put_slice(&this.prefix[this.position..this.position + len]);
+            this.position += len;
+            if this.position == this.prefix.len() {
+                this.prefix = Vec::new();
+                this.position = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Prefixed<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+pub async fn write_response<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    response: &Response,
+) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+        response.body.len(),
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn invalid(message: &'static str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// returns each chunk from a separate read, as a client sending the request in pieces
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl Chunks {
+        fn new(chunks: &[&[u8]]) -> Self {
+            Self(chunks.iter().map(|x| x.to_vec()).collect())
+        }
+    }
+
+    impl AsyncRead for Chunks {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let this = self.get_mut();
+            if let Some(mut chunk) = this.0.pop_front() {
+                let len = chunk.len().min(buf.remaining());
+                buf.put_slice(&chunk[..len]);
+                if len < chunk.len() {
+                    this.0.push_front(chunk.split_off(len));
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn error_kind(result: std::io::Result<Request>) -> std::io::ErrorKind {
+        result.err().expect("the request should be refused").kind()
+    }
+
+    #[tokio::test]
+    async fn a_head_split_across_reads_is_read_whole() {
+        let mut stream = Chunks::new(&[
+            b"GET /healthz?a=1&b HTTP/1.1\r",
+            b"\nHost: exemplo\r\nUpgrade: WebSocket\r\n",
+            b"\r",
+            b"\n",
+        ]);
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/healthz");
+        assert_eq!(request.query_param("a"), Some("1"));
+        assert_eq!(request.header("HOST"), Some("exemplo"));
+        assert!(request.is_websocket());
+        assert!(request.body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_body_is_read_up_to_its_content_length() {
+        let mut stream = Chunks::new(&[
+            b"POST /x HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123",
+            b"456789 and the next request",
+        ]);
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.body, b"0123456789");
+        let mut stream = Chunks::new(&[b"POST /x HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123"]);
+        assert_eq!(
+            error_kind(read_request(&mut stream).await),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[tokio::test]
+    async fn a_bad_content_length_is_refused() {
+        for value in ["-1", "abc", "1 0", ""] {
+            let head = format!("POST / HTTP/1.1\r\nContent-Length: {value}\r\n\r\n");
+            let mut stream = Chunks::new(&[head.as_bytes()]);
+            assert_eq!(
+                error_kind(read_request(&mut stream).await),
+                std::io::ErrorKind::InvalidData,
+                "{value:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn heads_and_bodies_over_the_limits_are_refused() {
+        let head = format!(
+            "GET / HTTP/1.1\r\nX-Grande: {}\r\n\r\n",
+            "a".repeat(2 * MAX_HEAD_SIZE)
+        );
+        let mut stream = Chunks::new(&[head.as_bytes()]);
+        assert_eq!(
+            error_kind(read_request(&mut stream).await),
+            std::io::ErrorKind::InvalidData
+        );
+        // refused before reading the body
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        let mut stream = Chunks::new(&[head.as_bytes()]);
+        assert_eq!(
+            error_kind(read_request(&mut stream).await),
+            std::io::ErrorKind::InvalidData
+        );
+        let head = format!("POST / HTTP/1.1\r\nContent-Length: {MAX_BODY_SIZE}\r\n\r\n");
+        let body = vec![b'x'; MAX_BODY_SIZE];
+        let mut stream = Chunks::new(&[head.as_bytes(), &body]);
+        assert_eq!(
+            read_request(&mut stream).await.unwrap().body.len(),
+            MAX_BODY_SIZE
+        );
+    }
+
+    #[tokio::test]
+    async fn a_peeked_request_is_read_again_from_the_stream() {
+        let bytes = b"GET /tcp HTTP/1.1\r\nUpgrade: websocket\r\n\r\nfirst frame";
+        let (mut stream, request) =
+            peek_request(Chunks::new(&[&bytes[..10], &bytes[10..], b" and more"])).await;
+        assert_eq!(request.unwrap().path, "/tcp");
+        let mut replayed = Vec::new();
+        stream.read_to_end(&mut replayed).await.unwrap();
+        assert_eq!(
+            replayed,
+            b"GET /tcp HTTP/1.1\r\nUpgrade: websocket\r\n\r\nfirst frame and more"
+        );
+        // what was read of an invalid request is kept too
+        let (mut stream, request) = peek_request(Chunks::new(&[b"GET\r\n\r\nrest"])).await;
+        assert!(request.is_err());
+        let mut replayed = Vec::new();
+        stream.read_to_end(&mut replayed).await.unwrap();
+        assert_eq!(replayed, b"GET\r\n\r\nrest");
+    }
+
+    #[tokio::test]
+    async fn an_invalid_request_is_answered_with_400() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        client
+            .write_all(b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n")
+            .await
+            .unwrap();
+        handle_connection(server, |_| async { Response::text(200, "ok\n") }).await;
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{response}"
+        );
+    }
+}
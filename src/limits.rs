@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// limits to protect the server from a single client, 0 disables each one
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// sessions in the session map, including the ones waiting for a websocket, and udp flows
+    pub max_sessions: usize,
+    pub max_sessions_per_ip: usize,
+    pub new_sessions_per_ip_per_minute: u32,
+    /// websocket handshakes started per second, across all ips
+    pub max_handshakes_per_sec: u32,
+}
+
+/// why a new session was refused
+#[derive(Debug, Clone, Copy)]
+pub enum LimitError {
+    TooManySessions,
+    TooManySessionsFromIp,
+    TooManyNewSessionsFromIp,
+}
+impl LimitError {
+    /// if the whole server is full, instead of only the ip being over its limits
+    pub fn is_global(self) -> bool {
+        matches!(self, LimitError::TooManySessions)
+    }
+}
+impl std::fmt::Display for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitError::TooManySessions => f.write_str("limite de sessões atingido"),
+            LimitError::TooManySessionsFromIp => f.write_str("limite de sessões por ip atingido"),
+            LimitError::TooManyNewSessionsFromIp => {
+                f.write_str("limite de novas sessões por minuto atingido")
+            }
+        }
+    }
+}
+
+/// the state needed to enforce `Limits`
+pub struct Limiter {
+    pub limits: Limits,
+    handshakes: Mutex<TokenBucket>,
+    usage: Mutex<Usage>,
+}
+
+#[derive(Default)]
+struct Usage {
+    sessions: usize,
+    per_ip: HashMap<IpAddr, IpUsage>,
+}
+
+struct IpUsage {
+    sessions: usize,
+    new_sessions: TokenBucket,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Self {
+        let handshakes = limits.max_handshakes_per_sec as u64;
+        Self {
+            limits,
+            handshakes: Mutex::new(TokenBucket::new(handshakes, handshakes)),
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+    /// takes a token for a new handshake, false if there were too many in the last second
+    pub fn try_handshake(&self) -> bool {
+        self.limits.max_handshakes_per_sec == 0 || self.handshakes.lock().unwrap().try_take(1)
+    }
+    /// counts a new session from the ip if it is within the limits, also counting it in the rate of new sessions
+    /// the slot is released when dropped, unless kept by the session map
+    pub fn try_add_session(&self, ip: IpAddr) -> Result<SessionSlot<'_>, LimitError> {
+        let limits = &self.limits;
+        let mut usage = self.usage.lock().unwrap();
+        if limits.max_sessions > 0 && usage.sessions >= limits.max_sessions {
+            return Err(LimitError::TooManySessions);
+        }
+        let ip_usage = usage.per_ip.entry(ip).or_insert_with(|| IpUsage {
+            sessions: 0,
+            new_sessions: TokenBucket::per_minute(limits.new_sessions_per_ip_per_minute),
+        });
+        if limits.max_sessions_per_ip > 0 && ip_usage.sessions >= limits.max_sessions_per_ip {
+            return Err(LimitError::TooManySessionsFromIp);
+        }
+        if limits.new_sessions_per_ip_per_minute > 0 && !ip_usage.new_sessions.try_take(1) {
+            return Err(LimitError::TooManyNewSessionsFromIp);
+        }
+        ip_usage.sessions += 1;
+        usage.sessions += 1;
+        Ok(SessionSlot {
+            limiter: Some(self),
+            ip,
+        })
+    }
+    pub fn remove_session(&self, ip: IpAddr) {
+        let mut usage = self.usage.lock().unwrap();
+        usage.sessions = usage.sessions.saturating_sub(1);
+        if let Some(ip_usage) = usage.per_ip.get_mut(&ip) {
+            ip_usage.sessions = ip_usage.sessions.saturating_sub(1);
+        }
+        // forgets the ips that are back to a full bucket, so the map does not grow forever
+        usage
+            .per_ip
+            .retain(|_, x| x.sessions > 0 || !x.new_sessions.is_full());
+    }
+}
+
+/// a session counted by `Limiter::try_add_session`
+pub struct SessionSlot<'a> {
+    limiter: Option<&'a Limiter>,
+    ip: IpAddr,
+}
+
+impl SessionSlot<'_> {
+    /// the session was added to the session map, that calls `remove_session` when it is removed
+    pub fn keep(mut self) {
+        self.limiter = None;
+    }
+}
+
+impl Drop for SessionSlot<'_> {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter {
+            limiter.remove_session(self.ip);
+        }
+    }
+}
+
+/// allows `rate` units per second, with bursts of up to `capacity`
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64, capacity: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            capacity: capacity.max(1) as f64,
+            tokens: capacity.max(1) as f64,
+            last: Instant::now(),
+        }
+    }
+    fn per_minute(rate: u32) -> Self {
+        let mut bucket = Self::new(0, rate as u64);
+        bucket.rate = rate as f64 / 60.0;
+        bucket
+    }
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+    pub fn try_take(&mut self, amount: u64) -> bool {
+        self.refill();
+        if self.tokens >= amount as f64 {
+            self.tokens -= amount as f64;
+            true
+        } else {
+            false
+        }
+    }
+    /// takes the amount, going into debt if needed, returns how long to wait until the debt is paid
+    pub fn reserve(&mut self, amount: u64) -> Duration {
+        self.refill();
+        self.tokens -= amount as f64;
+        if self.tokens < 0.0 && self.rate > 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+    const B: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn buckets_allow_bursts_up_to_their_capacity() {
+        let mut bucket = TokenBucket::new(1, 3);
+        assert!(bucket.try_take(2));
+        assert!(bucket.try_take(1));
+        assert!(!bucket.try_take(1));
+    }
+
+    #[test]
+    fn buckets_refill_with_time_up_to_their_capacity() {
+        let mut bucket = TokenBucket::new(1000, 10);
+        assert!(bucket.try_take(10));
+        assert!(!bucket.try_take(5));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!bucket.try_take(11));
+        assert!(bucket.try_take(10));
+    }
+
+    #[test]
+    fn reserving_more_than_available_waits_for_the_debt() {
+        let mut bucket = TokenBucket::new(1000, 100);
+        assert_eq!(bucket.reserve(100), Duration::ZERO);
+        let delay = bucket.reserve(100);
+        assert!(delay > Duration::from_millis(90) && delay <= Duration::from_millis(100));
+        assert!(bucket.reserve(100) > Duration::from_millis(190));
+    }
+
+    #[test]
+    fn zero_disables_each_limit() {
+        let limiter = Limiter::new(Limits::default());
+        for _ in 0..100 {
+            assert!(limiter.try_handshake());
+            limiter.try_add_session(A).unwrap().keep();
+        }
+    }
+
+    #[test]
+    fn handshakes_are_limited_per_second() {
+        let limiter = Limiter::new(Limits {
+            max_handshakes_per_sec: 2,
+            ..Default::default()
+        });
+        assert!(limiter.try_handshake());
+        assert!(limiter.try_handshake());
+        assert!(!limiter.try_handshake());
+    }
+
+    #[test]
+    fn the_server_limit_is_global() {
+        let limiter = Limiter::new(Limits {
+            max_sessions: 2,
+            ..Default::default()
+        });
+        let _a = limiter.try_add_session(A).unwrap();
+        let _b = limiter.try_add_session(B).unwrap();
+        let error = limiter.try_add_session(B).err().unwrap();
+        assert!(matches!(error, LimitError::TooManySessions));
+        assert!(error.is_global());
+    }
+
+    #[test]
+    fn slots_are_released_unless_kept() {
+        let limiter = Limiter::new(Limits {
+            max_sessions: 1,
+            ..Default::default()
+        });
+        drop(limiter.try_add_session(A).unwrap());
+        limiter.try_add_session(A).unwrap().keep();
+        assert!(limiter.try_add_session(B).is_err());
+        // the session map removes the sessions it kept
+        limiter.remove_session(A);
+        assert!(limiter.try_add_session(B).is_ok());
+    }
+
+    #[test]
+    fn sessions_are_counted_per_ip_until_removed() {
+        let limiter = Limiter::new(Limits {
+            max_sessions_per_ip: 1,
+            ..Default::default()
+        });
+        limiter.try_add_session(A).unwrap().keep();
+        let error = limiter.try_add_session(A).err().unwrap();
+        assert!(matches!(error, LimitError::TooManySessionsFromIp));
+        assert!(!error.is_global());
+        assert!(limiter.try_add_session(B).is_ok());
+        limiter.remove_session(A);
+        assert!(limiter.try_add_session(A).is_ok());
+    }
+
+    #[test]
+    fn new_sessions_are_limited_per_ip_per_minute() {
+        let limiter = Limiter::new(Limits {
+            new_sessions_per_ip_per_minute: 2,
+            ..Default::default()
+        });
+        assert!(limiter.try_add_session(A).is_ok());
+        assert!(limiter.try_add_session(A).is_ok());
+        let error = limiter.try_add_session(A).err().unwrap();
+        assert!(matches!(error, LimitError::TooManyNewSessionsFromIp));
+        assert!(limiter.try_add_session(B).is_ok());
+    }
+}
@@ -0,0 +1,124 @@
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{
+        format::{DefaultFields, Writer},
+        FormatFields,
+    },
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::config::LogConfig;
+
+/// must be kept alive until the end of the program, dropping it stops writing to the log file
+pub struct LogGuard {
+    _file: Option<tracing_appender::non_blocking::WorkerGuard>,
+}
+
+/// sets up the global logger, according to the `[log]` section of the config
+pub fn init(config: &LogConfig) -> LogGuard {
+    let filter = EnvFilter::try_new(&config.level).unwrap_or_else(|error| {
+        eprintln!("o nível de log {:?} não é válido: {error}", config.level);
+        EnvFilter::new("info")
+    });
+
+    let stdout = config
+        .stdout
+        .then(|| tracing_subscriber::fmt::layer().with_target(false));
+
+    let mut file_guard = None;
+    let file = config
+        .file
+        .then(|| {
+            let dir = crate::config::base_dir().ok()?.join("logs");
+            let appender = tracing_appender::rolling::Builder::new()
+                .rotation(tracing_appender::rolling::Rotation::DAILY)
+                .filename_prefix("ws_to_tcp")
+                .filename_suffix("log")
+                .max_log_files(config.max_files.max(1))
+                .build(&dir)
+                .map_err(|error| {
+                    eprintln!("erro ao criar o log em {}: {error}", dir.display());
+                })
+                .ok()?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            file_guard = Some(guard);
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .fmt_fields(FileFields::default())
+                    .with_writer(writer),
+            )
+        })
+        .flatten();
+
+    let journald = journald_layer(config.journald);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stdout)
+        .with(file)
+        .with(journald)
+        .with(tcp_over_ws::errors::layer())
+        .init();
+
+    LogGuard { _file: file_guard }
+}
+
+/// sets up the global logger writing only to stderr, for commands that use stdout for data
+pub fn init_stderr(level: &str) {
+    let filter = EnvFilter::try_new(level).unwrap_or_else(|error| {
+        eprintln!("o nível de log {level:?} não é válido: {error}");
+        EnvFilter::new("warn")
+    });
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .with_writer(std::io::stderr),
+        )
+        .init();
+}
+
+/// the span fields are formatted once per formatter type and reused, a separate type keeps the colors of stdout out of the file
+#[derive(Default)]
+struct FileFields(DefaultFields);
+impl<'writer> FormatFields<'writer> for FileFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn journald_layer<S>(enabled: bool) -> Option<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    if !enabled {
+        return None;
+    }
+    match tracing_journald::layer() {
+        Ok(layer) => Some(layer.with_syslog_identifier("ws_to_tcp".into()).boxed()),
+        Err(error) => {
+            eprintln!("erro ao conectar no journald: {error}");
+            None
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn journald_layer<S>(enabled: bool) -> Option<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    if enabled {
+        eprintln!("o journald só está disponível no linux");
+    }
+    None
+}
@@ -1,5 +1,6 @@
 mod cli;
 mod config;
+mod log;
 
 use async_tungstenite::tungstenite::client::IntoClientRequest;
 
@@ -23,26 +24,32 @@ fn main() {
 
     cli::cli();
 
+    let log_guard = log::init(&config::load_log_config());
+
     if cfg!(debug_assertions) {
         let connect_request = "ws://127.0.0.1:9601".into_client_request().unwrap();
         std::thread::spawn(move || {
-            let listen = ["127.0.0.1:19258".parse().unwrap()];
+            let listen = tcp_over_ws::addr::ListenAddr::Tcp(vec!["127.0.0.1:19258".parse().unwrap()]);
         
             let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
             let _enter_guard = rt.enter();
-            let Ok(server) = rt.block_on(tcp_over_ws::bind(&listen[..])) else {
+            let Ok(server) = rt.block_on(tcp_over_ws::bind(&listen)) else {
                 return;
             };
-            let _ = rt.block_on(tcp_over_ws::tcp_to_ws_service(connect_request, server, tcp_over_ws::DEFAULT_TIMEOUT_MS));
+            let _ = rt.block_on(tcp_over_ws::tcp_to_ws_service(connect_request, server, tcp_over_ws::ClientOptions::default()));
         });
     }
 
-    let (listen, connect_addr) = config::load_config().unwrap_or_else(|()| std::process::exit(1));
+    let Ok(config) = config::load_config() else {
+        drop(log_guard);
+        std::process::exit(1)
+    };
 
-    match tcp_over_ws::ws_to_tcp_service(connect_addr, listen) {
+    match tcp_over_ws::ws_to_tcp_service(config) {
         Ok(()) => {}
         Err(error) => {
-            println!("erro ao escutar: {error:?}");
+            tracing::error!("erro ao escutar: {error:?}");
+            drop(log_guard);
             std::process::exit(1)
         }
     }
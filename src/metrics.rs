@@ -0,0 +1,232 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{health::Health, pool::Pool};
+
+pub(crate) static SESSIONS_CREATED: AtomicU64 = AtomicU64::new(0);
+pub(crate) static SESSIONS_EXPIRED: AtomicU64 = AtomicU64::new(0);
+/// websockets attached to a session that already existed
+pub(crate) static WEBSOCKET_RECONNECTS: AtomicU64 = AtomicU64::new(0);
+/// bytes read from the tcp stream, to be sent over the websocket
+pub(crate) static BYTES_TCP_TO_WS: AtomicU64 = AtomicU64::new(0);
+/// bytes received from the websocket, written to the tcp stream
+pub(crate) static BYTES_WS_TO_TCP: AtomicU64 = AtomicU64::new(0);
+pub(crate) static ACK_ERRORS: AtomicU64 = AtomicU64::new(0);
+pub(crate) static BACKEND_CONNECT_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// connections and sessions refused by the limits
+pub(crate) static LIMIT_REJECTIONS: AtomicU64 = AtomicU64::new(0);
+/// destinations asked by clients and refused by the target policy
+pub(crate) static TARGET_DENIALS: AtomicU64 = AtomicU64::new(0);
+/// udp flows relayed by the server
+pub(crate) static UDP_FLOWS: AtomicU64 = AtomicU64::new(0);
+/// bytes held in `Session::buffer` across all sessions
+pub(crate) static SESSION_BUFFER_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn add(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+pub(crate) fn sub(counter: &AtomicU64, value: u64) {
+    counter.fetch_sub(value, Ordering::Relaxed);
+}
+pub(crate) fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+/// renders all metrics in the prometheus text format
+pub fn render(active_sessions: usize, pool: &Pool) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{name}{labels} {value}");
+        }
+    };
+    metric(
+        "tow_sessions_active",
+        "gauge",
+        "Sessions currently in the session map.",
+        &[("", active_sessions as u64)],
+    );
+    metric(
+        "tow_sessions_created_total",
+        "counter",
+        "Sessions created.",
+        &[("", get(&SESSIONS_CREATED))],
+    );
+    metric(
+        "tow_sessions_expired_total",
+        "counter",
+        "Sessions removed from the session map after their timeout.",
+        &[("", get(&SESSIONS_EXPIRED))],
+    );
+    metric(
+        "tow_websocket_reconnects_total",
+        "counter",
+        "Websockets that resumed an existing session.",
+        &[("", get(&WEBSOCKET_RECONNECTS))],
+    );
+    metric(
+        "tow_bytes_total",
+        "counter",
+        "Bytes tunneled, by direction.",
+        &[
+            ("{direction=\"tcp_to_ws\"}", get(&BYTES_TCP_TO_WS)),
+            ("{direction=\"ws_to_tcp\"}", get(&BYTES_WS_TO_TCP)),
+        ],
+    );
+    metric(
+        "tow_ack_errors_total",
+        "counter",
+        "Sessions killed because of an invalid ack.",
+        &[("", get(&ACK_ERRORS))],
+    );
+    metric(
+        "tow_backend_connect_failures_total",
+        "counter",
+        "Failed connection attempts to tcp services.",
+        &[("", get(&BACKEND_CONNECT_FAILURES))],
+    );
+    metric(
+        "tow_limit_rejections_total",
+        "counter",
+        "Connections and sessions refused by the configured limits.",
+        &[("", get(&LIMIT_REJECTIONS))],
+    );
+    metric(
+        "tow_target_denials_total",
+        "counter",
+        "Destinations asked by clients and refused by the target policy.",
+        &[("", get(&TARGET_DENIALS))],
+    );
+    metric(
+        "tow_udp_flows",
+        "gauge",
+        "Udp flows relayed by the server.",
+        &[("", get(&UDP_FLOWS))],
+    );
+    metric(
+        "tow_session_buffer_bytes",
+        "gauge",
+        "Bytes held in session buffers waiting to be acknowledged.",
+        &[("", get(&SESSION_BUFFER_BYTES))],
+    );
+    let labels = pool
+        .backends
+        .iter()
+        .map(|x| format!("{{target=\"{}\"}}", x.target))
+        .collect::<Vec<_>>();
+    metric(
+        "tow_backend_up",
+        "gauge",
+        "If the backend is considered up, by the health checks and recent connection failures.",
+        &pool
+            .backends
+            .iter()
+            .zip(&labels)
+            .map(|(x, labels)| (labels.as_str(), x.is_healthy() as u64))
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        "tow_backend_health_check_up",
+        "gauge",
+        "The result of the last health check of the backend, absent if not checked yet.",
+        &pool
+            .backends
+            .iter()
+            .zip(&labels)
+            .filter(|(x, _)| x.health() != Health::Unknown)
+            .map(|(x, labels)| (labels.as_str(), (x.health() == Health::Up) as u64))
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        "tow_backend_active_sessions",
+        "gauge",
+        "Sessions connected to the backend.",
+        &pool
+            .backends
+            .iter()
+            .zip(&labels)
+            .map(|(x, labels)| (labels.as_str(), x.active() as u64))
+            .collect::<Vec<_>>(),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        addr::{Endpoint, Target},
+        pool::Backend,
+    };
+
+    /// the samples of the rendered metrics by name and labels, and the type of each metric
+    fn parse(text: &str) -> (HashMap<&str, u64>, HashMap<&str, &str>) {
+        let mut samples = HashMap::new();
+        let mut types = HashMap::new();
+        for line in text.lines() {
+            if let Some(line) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = line.split_once(' ').unwrap();
+                types.insert(name, kind);
+            } else if !line.starts_with('#') {
+                let (sample, value) = line.rsplit_once(' ').unwrap();
+                samples.insert(sample, value.parse().unwrap());
+            }
+        }
+        (samples, types)
+    }
+
+    fn pool() -> Pool {
+        let backend = |port| {
+            Backend::new(
+                Endpoint::Tcp(Target {
+                    host: "10.0.0.1".to_owned(),
+                    port,
+                }),
+                1,
+            )
+        };
+        let pool = Pool::new(Default::default(), vec![backend(1), backend(2)], Vec::new());
+        pool.backends[1].set_health(Health::Down);
+        pool
+    }
+
+    #[test]
+    fn every_sample_has_a_help_and_a_type() {
+        let text = render(0, &pool());
+        let (samples, types) = parse(&text);
+        for sample in samples.keys() {
+            let name = sample.split('{').next().unwrap();
+            assert!(text.contains(&format!("# HELP {name} ")), "{name}");
+            let kind = types[name];
+            assert_eq!(name.ends_with("_total"), kind == "counter", "{name}");
+        }
+    }
+
+    #[test]
+    fn samples_are_labeled_by_direction_and_backend() {
+        let text = render(7, &pool());
+        let (samples, _) = parse(&text);
+        assert_eq!(samples["tow_sessions_active"], 7);
+        for labels in ["{direction=\"tcp_to_ws\"}", "{direction=\"ws_to_tcp\"}"] {
+            assert!(samples.contains_key(format!("tow_bytes_total{labels}").as_str()));
+        }
+        assert_eq!(samples["tow_backend_up{target=\"10.0.0.1:1\"}"], 1);
+        assert_eq!(samples["tow_backend_up{target=\"10.0.0.1:2\"}"], 0);
+        assert_eq!(
+            samples["tow_backend_active_sessions{target=\"10.0.0.1:1\"}"],
+            0
+        );
+        // absent until the backend is checked
+        assert!(!samples.contains_key("tow_backend_health_check_up{target=\"10.0.0.1:1\"}"));
+        assert_eq!(
+            samples["tow_backend_health_check_up{target=\"10.0.0.1:2\"}"],
+            0
+        );
+    }
+}
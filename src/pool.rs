@@ -0,0 +1,358 @@
+use std::{
+    sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    addr::Endpoint,
+    connect,
+    health::{Health, HealthCheck},
+    metrics, proxy_protocol,
+    stream::Stream,
+};
+
+pub const DEFAULT_MAX_FAILURES: u32 = 3;
+pub const DEFAULT_DOWN_TIME_MS: u64 = 30_000;
+pub const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+    /// random, weighted by `Backend::weight`
+    Random,
+}
+impl std::str::FromStr for Strategy {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "least-connections" => Ok(Strategy::LeastConnections),
+            "random" => Ok(Strategy::Random),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Backend {
+    pub target: Endpoint,
+    pub weight: u32,
+    /// the amount of sessions currently connected to this backend
+    active: AtomicUsize,
+    /// the amount of consecutive failed connection attempts
+    failures: AtomicU32,
+    down_until: std::sync::Mutex<Option<Instant>>,
+    /// the result of the last active health check, see `Health`
+    health: AtomicU8,
+}
+impl Backend {
+    pub fn new(target: Endpoint, weight: u32) -> Self {
+        Self {
+            target,
+            weight: weight.max(1),
+            active: AtomicUsize::new(0),
+            failures: AtomicU32::new(0),
+            down_until: std::sync::Mutex::new(None),
+            health: AtomicU8::new(Health::Unknown as u8),
+        }
+    }
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+    pub fn health(&self) -> Health {
+        match self.health.load(Ordering::Relaxed) {
+            x if x == Health::Up as u8 => Health::Up,
+            x if x == Health::Down as u8 => Health::Down,
+            _ => Health::Unknown,
+        }
+    }
+    /// returns the previous health
+    pub(crate) fn set_health(&self, health: Health) -> Health {
+        let previous = self.health();
+        self.health.store(health as u8, Ordering::Relaxed);
+        if health == Health::Up {
+            self.failures.store(0, Ordering::Relaxed);
+            *self.down_until.lock().unwrap() = None;
+        }
+        previous
+    }
+    /// false if the backend failed its last health check or too many connection attempts in a row
+    pub fn is_healthy(&self) -> bool {
+        self.health() != Health::Down
+            && self
+                .down_until
+                .lock()
+                .unwrap()
+                .is_none_or(|x| x <= Instant::now())
+    }
+}
+
+/// a group of backends that sessions are distributed across, plus fallback targets used when none of them accept
+pub struct Pool {
+    pub strategy: Strategy,
+    pub backends: Vec<Backend>,
+    pub fallback: Vec<Endpoint>,
+    /// consecutive connection failures before a backend is marked as down
+    pub max_failures: u32,
+    /// how long a backend stays marked as down before being tried again
+    pub down_time: Duration,
+    /// how long to wait for the connection to each target
+    pub connect_timeout: Duration,
+    pub health_check: Option<HealthCheck>,
+    /// the PROXY protocol header sent before the data of each session, not sent if `None`
+    pub proxy_protocol: Option<proxy_protocol::Version>,
+    /// the backends and fallback targets the header is sent to, all of them if empty
+    pub proxy_protocol_targets: Vec<Endpoint>,
+    next: AtomicUsize,
+}
+
+/// marks a session as connected to a backend of a pool, released on drop
+pub struct Lease {
+    pool: &'static Pool,
+    index: usize,
+}
+impl Lease {
+    pub fn backend(&self) -> &'static Backend {
+        &self.pool.backends[self.index]
+    }
+}
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.backend().active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Pool {
+    pub fn new(strategy: Strategy, backends: Vec<Backend>, fallback: Vec<Endpoint>) -> Self {
+        Self {
+            strategy,
+            backends,
+            fallback,
+            max_failures: DEFAULT_MAX_FAILURES,
+            down_time: Duration::from_millis(DEFAULT_DOWN_TIME_MS),
+            connect_timeout: Duration::from_millis(DEFAULT_CONNECT_TIMEOUT_MS),
+            health_check: None,
+            proxy_protocol: None,
+            proxy_protocol_targets: Vec::new(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// connects the session to a backend, the one picked by the strategy is tried first, then the other healthy backends, then the fallback targets
+    /// returns the endpoint connected to, with the lease if it is a backend
+    /// a session connects only once, a resumed session keeps its connection, so there is no affinity to keep between connections
+    pub async fn connect(
+        &'static self,
+    ) -> std::io::Result<(Stream, &'static Endpoint, Option<Lease>)> {
+        let mut last_error = None;
+        for index in self.candidates() {
+            let backend = &self.backends[index];
+            backend.active.fetch_add(1, Ordering::Relaxed);
+            let lease = Lease { pool: self, index };
+            match connect::connect_endpoint(&backend.target, self.connect_timeout).await {
+                Ok(stream) => {
+                    backend.failures.store(0, Ordering::Relaxed);
+                    return Ok((stream, &backend.target, Some(lease)));
+                }
+                Err(error) => {
+                    tracing::warn!("erro ao conectar em {}: {error:?}", backend.target);
+                    metrics::add(&metrics::BACKEND_CONNECT_FAILURES, 1);
+                    self.mark_failure(backend);
+                    last_error = Some(error);
+                }
+            }
+        }
+        if self.fallback.is_empty() {
+            if let Some(error) = last_error {
+                return Err(error);
+            }
+        }
+        connect::connect_any(&self.fallback, self.connect_timeout)
+            .await
+            .map(|(stream, endpoint)| (stream, endpoint, None))
+    }
+
+    /// the version of the PROXY protocol the endpoint expects, if any
+    pub fn proxy_protocol(&self, endpoint: &Endpoint) -> Option<proxy_protocol::Version> {
+        self.proxy_protocol.filter(|_| {
+            self.proxy_protocol_targets.is_empty() || self.proxy_protocol_targets.contains(endpoint)
+        })
+    }
+
+    /// false if every backend is known to be down and there is no fallback, new sessions are refused in this case
+    pub fn is_available(&self) -> bool {
+        !self.fallback.is_empty() || self.backends.iter().any(Backend::is_healthy)
+    }
+
+    fn mark_failure(&self, backend: &Backend) {
+        let failures = backend.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.max_failures {
+            let mut down_until = backend.down_until.lock().unwrap();
+            if down_until.is_none_or(|x| x <= Instant::now()) {
+                tracing::warn!(
+                    "{} marcado como fora do ar após {failures} falhas",
+                    backend.target
+                );
+            }
+            *down_until = Some(Instant::now() + self.down_time);
+        }
+    }
+
+    /// the order in which the backends should be tried for a new session
+    fn candidates(&self) -> Vec<usize> {
+        let mut healthy = (0..self.backends.len())
+            .filter(|&x| self.backends[x].is_healthy())
+            .collect::<Vec<_>>();
+        if healthy.is_empty() {
+            if !self.fallback.is_empty() {
+                return Vec::new();
+            }
+            healthy = (0..self.backends.len()).collect();
+        }
+        if healthy.is_empty() {
+            return healthy;
+        }
+        let first = self.pick(&healthy);
+        healthy.retain(|&x| x != first);
+        healthy.insert(0, first);
+        healthy
+    }
+
+    fn pick(&self, healthy: &[usize]) -> usize {
+        match self.strategy {
+            Strategy::RoundRobin => {
+                healthy[self.next.fetch_add(1, Ordering::Relaxed) % healthy.len()]
+            }
+            Strategy::LeastConnections => *healthy
+                .iter()
+                .min_by_key(|&&x| self.backends[x].active())
+                .unwrap(),
+            Strategy::Random => {
+                let total = healthy
+                    .iter()
+                    .map(|&x| self.backends[x].weight as u64)
+                    .sum::<u64>();
+                let mut choice = rand::random_range(0..total);
+                for &index in healthy {
+                    let weight = self.backends[index].weight as u64;
+                    if choice < weight {
+                        return index;
+                    }
+                    choice -= weight;
+                }
+                healthy[0]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::addr::Target;
+
+    fn pool_of(strategy: Strategy, weights: &[u32], fallback: &[u16]) -> Pool {
+        let target = |port| {
+            Endpoint::Tcp(Target {
+                host: "127.0.0.1".to_owned(),
+                port,
+            })
+        };
+        let backends = weights
+            .iter()
+            .enumerate()
+            .map(|(index, &weight)| Backend::new(target(index as u16 + 1), weight))
+            .collect();
+        Pool::new(
+            strategy,
+            backends,
+            fallback.iter().copied().map(target).collect(),
+        )
+    }
+
+    #[test]
+    fn round_robin_takes_turns_and_tries_the_others_after() {
+        let pool = pool_of(Strategy::RoundRobin, &[1, 1, 1], &[]);
+        assert_eq!(pool.candidates(), [0, 1, 2]);
+        assert_eq!(pool.candidates(), [1, 0, 2]);
+        assert_eq!(pool.candidates(), [2, 0, 1]);
+        assert_eq!(pool.candidates(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn least_connections_picks_the_least_busy_backend() {
+        let pool = pool_of(Strategy::LeastConnections, &[1, 1, 1], &[]);
+        pool.backends[0].active.store(2, Ordering::Relaxed);
+        pool.backends[1].active.store(1, Ordering::Relaxed);
+        pool.backends[2].active.store(3, Ordering::Relaxed);
+        assert_eq!(pool.candidates()[0], 1);
+        pool.backends[1].active.store(4, Ordering::Relaxed);
+        assert_eq!(pool.candidates()[0], 0);
+    }
+
+    #[test]
+    fn random_follows_the_weights() {
+        let pool = pool_of(Strategy::Random, &[1, 99, 0], &[]);
+        // a weight of 0 counts as 1
+        assert_eq!(pool.backends[2].weight, 1);
+        let mut picks = [0; 3];
+        for _ in 0..1000 {
+            picks[pool.candidates()[0]] += 1;
+        }
+        assert!(picks[1] > 900, "{picks:?}");
+        assert_eq!(picks.iter().sum::<i32>(), 1000);
+    }
+
+    #[test]
+    fn backends_are_marked_down_after_consecutive_failures() {
+        let pool = pool_of(Strategy::RoundRobin, &[1, 1], &[]);
+        for _ in 1..pool.max_failures {
+            pool.mark_failure(&pool.backends[0]);
+        }
+        assert!(pool.backends[0].is_healthy());
+        pool.mark_failure(&pool.backends[0]);
+        assert!(!pool.backends[0].is_healthy());
+        assert_eq!(pool.candidates(), [1]);
+        assert_eq!(pool.candidates(), [1]);
+    }
+
+    #[test]
+    fn down_backends_are_still_tried_without_fallbacks() {
+        let pool = pool_of(Strategy::RoundRobin, &[1, 1], &[]);
+        for backend in &pool.backends {
+            for _ in 0..pool.max_failures {
+                pool.mark_failure(backend);
+            }
+        }
+        assert_eq!(pool.candidates(), [0, 1]);
+        // the fallbacks are tried instead
+        let pool = pool_of(Strategy::RoundRobin, &[1], &[9]);
+        for _ in 0..pool.max_failures {
+            pool.mark_failure(&pool.backends[0]);
+        }
+        assert!(pool.candidates().is_empty());
+    }
+
+    #[test]
+    fn backends_come_back_after_the_down_time() {
+        let mut pool = pool_of(Strategy::RoundRobin, &[1], &[]);
+        pool.down_time = Duration::ZERO;
+        for _ in 0..pool.max_failures {
+            pool.mark_failure(&pool.backends[0]);
+        }
+        assert!(pool.backends[0].is_healthy());
+    }
+
+    #[test]
+    fn a_passing_health_check_forgets_the_failures() {
+        let pool = pool_of(Strategy::RoundRobin, &[1], &[]);
+        for _ in 0..pool.max_failures {
+            pool.mark_failure(&pool.backends[0]);
+        }
+        assert!(!pool.is_available());
+        pool.backends[0].set_health(Health::Up);
+        assert!(pool.backends[0].is_healthy());
+        assert!(pool.is_available());
+    }
+}
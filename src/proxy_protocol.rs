@@ -0,0 +1,277 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// the start of every v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// the longest v1 header, including the `\r\n`
+const V1_MAX_LEN: usize = 107;
+/// how long a load balancer has to send the header after connecting
+pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// the version of the HAProxy PROXY protocol, v1 is text and v2 is binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl std::str::FromStr for Version {
+    type Err = String;
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text.trim() {
+            "v1" | "1" => Ok(Version::V1),
+            "v2" | "2" => Ok(Version::V2),
+            text => Err(format!("versão do protocolo PROXY inválida: {text:?}")),
+        }
+    }
+}
+
+/// the header announcing a connection from `source` to `destination`
+/// without the addresses it announces a connection of the proxy itself, as for health checks
+pub fn header(version: Version, addrs: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    // both addresses must be of the same family
+    let addrs = addrs.map(|(source, destination)| {
+        let (source, destination) = (unmap(source), unmap(destination));
+        if source.is_ipv4() == destination.is_ipv4() {
+            (source, destination)
+        } else {
+            (to_ipv6(source), to_ipv6(destination))
+        }
+    });
+    match version {
+        Version::V1 => match addrs {
+            Some((source, destination)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if source.is_ipv4() { "TCP4" } else { "TCP6" },
+                source.ip(),
+                destination.ip(),
+                source.port(),
+                destination.port()
+            )
+            .into_bytes(),
+            None => b"PROXY UNKNOWN\r\n".to_vec(),
+        },
+        Version::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            match addrs {
+                Some((source, destination)) => {
+                    // PROXY over tcp
+                    header.push(0x21);
+                    let mut body = Vec::with_capacity(36);
+                    match (source.ip(), destination.ip()) {
+                        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                            header.push(0x11);
+                            body.extend_from_slice(&source.octets());
+                            body.extend_from_slice(&destination.octets());
+                        }
+                        (source, destination) => {
+                            header.push(0x21);
+                            body.extend_from_slice(&to_ipv6_ip(source).octets());
+                            body.extend_from_slice(&to_ipv6_ip(destination).octets());
+                        }
+                    }
+                    body.extend_from_slice(&source.port().to_be_bytes());
+                    body.extend_from_slice(&destination.port().to_be_bytes());
+                    header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+                    header.extend_from_slice(&body);
+                }
+                None => {
+                    // LOCAL, unspecified family
+                    header.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+                }
+            }
+            header
+        }
+    }
+}
+
+/// reads the header a load balancer sends before the data of the connection, v1 or v2
+/// returns the source and destination of the original connection, none for connections of the load balancer itself
+pub async fn read_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> std::io::Result<Option<(SocketAddr, SocketAddr)>> {
+    let invalid = |message: &str| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("cabeçalho PROXY inválido: {message}"),
+        )
+    };
+    let first = stream.read_u8().await?;
+    if first == b'P' {
+        // reads one byte at a time, so nothing after the header is consumed
+        let mut line = vec![first];
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                return Err(invalid("muito grande"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("texto"))?;
+        let parts = line.split(' ').collect::<Vec<_>>();
+        return match parts[..] {
+            ["PROXY", "UNKNOWN", ..] => Ok(None),
+            ["PROXY", "TCP4" | "TCP6", source, destination, source_port, destination_port] => {
+                let addr = |ip: &str, port: &str| -> Option<SocketAddr> {
+                    Some(SocketAddr::new(ip.parse().ok()?, port.parse().ok()?))
+                };
+                match (
+                    addr(source, source_port),
+                    addr(destination, destination_port),
+                ) {
+                    (Some(source), Some(destination)) => Ok(Some((source, destination))),
+                    _ => Err(invalid("endereços")),
+                }
+            }
+            _ => Err(invalid(line)),
+        };
+    }
+    let mut fixed = [0u8; 16];
+    fixed[0] = first;
+    stream.read_exact(&mut fixed[1..]).await?;
+    if fixed[..12] != V2_SIGNATURE || fixed[12] >> 4 != 2 {
+        return Err(invalid("assinatura"));
+    }
+    let mut body = vec![0u8; u16::from_be_bytes([fixed[14], fixed[15]]) as usize];
+    stream.read_exact(&mut body).await?;
+    // LOCAL, or an unknown family, keeps the address of the connection
+    if fixed[12] & 0x0f == 0 {
+        return Ok(None);
+    }
+    match fixed[13] >> 4 {
+        0x1 if body.len() >= 12 => {
+            let ip = |x: &[u8]| IpAddr::V4(Ipv4Addr::new(x[0], x[1], x[2], x[3]));
+            let port = |x: &[u8]| u16::from_be_bytes([x[0], x[1]]);
+            Ok(Some((
+                SocketAddr::new(ip(&body[0..4]), port(&body[8..10])),
+                SocketAddr::new(ip(&body[4..8]), port(&body[10..12])),
+            )))
+        }
+        0x2 if body.len() >= 36 => {
+            let ip = |x: &[u8]| IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(x).unwrap()));
+            let port = |x: &[u8]| u16::from_be_bytes([x[0], x[1]]);
+            Ok(Some((
+                SocketAddr::new(ip(&body[0..16]), port(&body[32..34])),
+                SocketAddr::new(ip(&body[16..32]), port(&body[34..36])),
+            )))
+        }
+        0x0 | 0x3 => Ok(None),
+        _ => Err(invalid("família de endereços")),
+    }
+}
+
+/// turns ipv4 mapped ipv6 addresses back into ipv4
+fn unmap(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), addr.port()),
+            None => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(IpAddr::V6(to_ipv6_ip(addr.ip())), addr.port())
+}
+
+fn to_ipv6_ip(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(text: &str) -> SocketAddr {
+        text.parse().unwrap()
+    }
+
+    async fn read(mut bytes: &[u8]) -> std::io::Result<Option<(SocketAddr, SocketAddr)>> {
+        read_header(&mut bytes).await
+    }
+
+    #[tokio::test]
+    async fn headers_are_read_as_they_are_written() {
+        let v4 = (addr("1.2.3.4:5555"), addr("10.0.0.1:9601"));
+        let v6 = (addr("[fd00::1]:5555"), addr("[fd00::2]:9601"));
+        for version in [Version::V1, Version::V2] {
+            for addrs in [v4, v6] {
+                let header = header(version, Some(addrs));
+                assert_eq!(read(&header).await.unwrap(), Some(addrs), "{version:?}");
+            }
+            assert_eq!(read(&header(version, None)).await.unwrap(), None);
+            // mapped addresses are sent as ipv4, mixed families as ipv6
+            let mapped = (addr("[::ffff:1.2.3.4]:5555"), addr("10.0.0.1:9601"));
+            assert_eq!(
+                read(&header(version, Some(mapped))).await.unwrap(),
+                Some(v4)
+            );
+            let mixed = (addr("1.2.3.4:5555"), addr("[fd00::2]:9601"));
+            let expected = (addr("[::ffff:1.2.3.4]:5555"), addr("[fd00::2]:9601"));
+            assert_eq!(
+                read(&header(version, Some(mixed))).await.unwrap(),
+                Some(expected)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn the_bytes_after_the_header_are_left_in_the_stream() {
+        for version in [Version::V1, Version::V2] {
+            let mut bytes = header(version, Some((addr("1.2.3.4:1"), addr("5.6.7.8:2"))));
+            bytes.extend_from_slice(b"GET / HTTP/1.1\r\n");
+            let mut stream = &bytes[..];
+            read_header(&mut stream).await.unwrap();
+            assert_eq!(stream, b"GET / HTTP/1.1\r\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn a_header_cut_short_fails_at_its_end() {
+        for version in [Version::V1, Version::V2] {
+            let full = header(version, Some((addr("[fd00::1]:1"), addr("[fd00::2]:2"))));
+            for len in 0..full.len() {
+                let error = read(&full[..len]).await.unwrap_err();
+                assert_eq!(
+                    error.kind(),
+                    ErrorKind::UnexpectedEof,
+                    "{version:?} {len} bytes"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_headers_are_refused() {
+        for bytes in [
+            &b"PROXY TCP4 1.2.3.4 5.6.7.8 1\r\n"[..],
+            b"PROXY TCP4 1.2.3.4 5.6.7.8 1 70000\r\n",
+            b"PROXY TCP4 um 5.6.7.8 1 2\r\n",
+            b"PROXY UDP4 1.2.3.4 5.6.7.8 1 2\r\n",
+            b"PROXY\r\n",
+            b"GET / HTTP/1.1\r\nHost: x\r\n\r\n",
+            b"\r\n\r\n\0\r\nQUIX\n\x21\x11\0\0",
+            // version 1 in the binary header
+            b"\r\n\r\n\0\r\nQUIT\n\x11\x11\0\0",
+            // unknown address family
+            b"\r\n\r\n\0\r\nQUIT\n\x21\x51\0\0",
+        ] {
+            let error = read(bytes).await.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{bytes:?}");
+        }
+        let mut long = b"PROXY TCP4 ".to_vec();
+        long.resize(200, b'1');
+        assert_eq!(
+            read(&long).await.unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::limits::TokenBucket;
+
+/// bandwidth limits in bytes per second, 0 disables each one
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+pub struct Shaping {
+    /// bytes read from the tcp stream and sent over the websocket
+    pub tcp_to_ws: u64,
+    /// bytes received from the websocket and written to the tcp stream
+    pub ws_to_tcp: u64,
+    /// both directions added
+    pub total: u64,
+}
+
+impl Shaping {
+    pub fn is_unlimited(&self) -> bool {
+        self.tcp_to_ws == 0 && self.ws_to_tcp == 0 && self.total == 0
+    }
+}
+
+/// token buckets enforcing a `Shaping`, shared by every session it applies to
+pub struct Shaper {
+    pub shaping: Shaping,
+    tcp_to_ws: Option<Mutex<TokenBucket>>,
+    ws_to_tcp: Option<Mutex<TokenBucket>>,
+    total: Option<Mutex<TokenBucket>>,
+}
+
+impl Shaper {
+    pub fn new(shaping: Shaping) -> Self {
+        // a burst of one second, the minimum for a full websocket message to pass
+        let bucket = |rate: u64| (rate > 0).then(|| Mutex::new(TokenBucket::new(rate, rate)));
+        Self {
+            shaping,
+            tcp_to_ws: bucket(shaping.tcp_to_ws),
+            ws_to_tcp: bucket(shaping.ws_to_tcp),
+            total: bucket(shaping.total),
+        }
+    }
+    /// `None` if the shaping has no limits, so there is nothing to enforce
+    pub fn new_if_limited(shaping: Shaping) -> Option<Self> {
+        (!shaping.is_unlimited()).then(|| Self::new(shaping))
+    }
+    fn reserve(&self, direction: &Option<Mutex<TokenBucket>>, bytes: u64) -> Duration {
+        [direction, &self.total]
+            .into_iter()
+            .flatten()
+            .map(|x| x.lock().unwrap().reserve(bytes))
+            .max()
+            .unwrap_or(Duration::ZERO)
+    }
+}
+
+/// how long the bytes read from the tcp stream must wait before all the shapers let them go on
+pub fn tcp_to_ws(shapers: &[Arc<Shaper>], bytes: u64) -> Duration {
+    longest(shapers.iter().map(|x| x.reserve(&x.tcp_to_ws, bytes)))
+}
+
+/// how long the bytes received from the websocket must wait before all the shapers let them go on
+pub fn ws_to_tcp(shapers: &[Arc<Shaper>], bytes: u64) -> Duration {
+    longest(shapers.iter().map(|x| x.reserve(&x.ws_to_tcp, bytes)))
+}
+
+fn longest(delays: impl Iterator<Item = Duration>) -> Duration {
+    delays.max().unwrap_or(Duration::ZERO)
+}
+
+/// sleeps for a delay given by the shapers, without yielding when there is nothing to wait for
+pub async fn wait(delay: Duration) {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// asserts the delay is about `secs`, allowing for the time passed since the reservation
+    fn assert_about(delay: Duration, secs: f64) {
+        let delay = delay.as_secs_f64();
+        assert!(delay <= secs && delay > secs - 0.05, "{delay} != {secs}");
+    }
+
+    #[test]
+    fn unlimited_shaping_has_no_shaper() {
+        assert!(Shaper::new_if_limited(Shaping::default()).is_none());
+        let shaping = Shaping {
+            total: 1,
+            ..Default::default()
+        };
+        assert!(Shaper::new_if_limited(shaping).is_some());
+    }
+
+    #[test]
+    fn directions_are_limited_separately() {
+        let shaper = Shaper::new(Shaping {
+            tcp_to_ws: 100,
+            ..Default::default()
+        });
+        assert_eq!(shaper.reserve(&shaper.tcp_to_ws, 100), Duration::ZERO);
+        assert_about(shaper.reserve(&shaper.tcp_to_ws, 50), 0.5);
+        assert_eq!(shaper.reserve(&shaper.ws_to_tcp, 1_000_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn the_total_is_shared_by_both_directions() {
+        let shaper = Shaper::new(Shaping {
+            tcp_to_ws: 1000,
+            total: 100,
+            ..Default::default()
+        });
+        assert_eq!(shaper.reserve(&shaper.tcp_to_ws, 100), Duration::ZERO);
+        assert_about(shaper.reserve(&shaper.ws_to_tcp, 100), 1.0);
+    }
+
+    #[test]
+    fn the_slowest_limit_decides_the_delay() {
+        let shaper = Shaper::new(Shaping {
+            tcp_to_ws: 100,
+            total: 1000,
+            ..Default::default()
+        });
+        assert_eq!(shaper.reserve(&shaper.tcp_to_ws, 100), Duration::ZERO);
+        assert_about(shaper.reserve(&shaper.tcp_to_ws, 100), 1.0);
+    }
+
+    #[tokio::test]
+    async fn sessions_wait_for_every_shaper() {
+        let shapers = [
+            Arc::new(Shaper::new(Shaping {
+                ws_to_tcp: 1000,
+                ..Default::default()
+            })),
+            Arc::new(Shaper::new(Shaping {
+                ws_to_tcp: 100,
+                ..Default::default()
+            })),
+        ];
+        let start = std::time::Instant::now();
+        wait(ws_to_tcp(&shapers, 100)).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        wait(ws_to_tcp(&shapers, 10)).await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert_eq!(tcp_to_ws(&shapers, 1_000_000), Duration::ZERO);
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::addr::Target;
+
+/// how long a program has to send its request after connecting to the local proxy
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// what a local listener does with its connections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListenMode {
+    /// forwards every connection to the tcp service of the server
+    #[default]
+    Forward,
+    /// works as a socks5 or http `CONNECT` proxy, the server connects to the destination asked by each connection
+    Proxy,
+    /// receives udp datagrams instead of tcp connections, forwarded to the udp service of the server
+    Udp,
+}
+
+impl std::str::FromStr for ListenMode {
+    type Err = String;
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text.trim() {
+            "" | "forward" => Ok(ListenMode::Forward),
+            "proxy" | "socks" | "socks5" => Ok(ListenMode::Proxy),
+            "udp" => Ok(ListenMode::Udp),
+            text => Err(format!("modo de túnel inválido: {text:?}")),
+        }
+    }
+}
+
+/// the protocol a connection to the local proxy used, to answer in the same protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Socks5,
+    HttpConnect,
+}
+
+/// why the connection to the destination was not made, answered to the program in its protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// the server did not allow the destination
+    NotAllowed,
+    /// the server could not be reached, or refused the session
+    Unavailable,
+}
+
+/// reads the request of a connection to the local proxy, returning the destination asked
+/// socks5 without authentication and http `CONNECT` are accepted, told apart by the first byte
+pub async fn read_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> std::io::Result<(Protocol, Target)> {
+    tokio::time::timeout(REQUEST_TIMEOUT, read_request_inner(stream))
+        .await
+        .unwrap_or_else(|_| {
+            Err(Error::new(
+                ErrorKind::TimedOut,
+                "tempo esgotado esperando o pedido do proxy",
+            ))
+        })
+}
+
+async fn read_request_inner<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> std::io::Result<(Protocol, Target)> {
+    let first = stream.read_u8().await?;
+    if first == 0x05 {
+        read_socks5(stream)
+            .await
+            .map(|target| (Protocol::Socks5, target))
+    } else {
+        read_http_connect(stream, first)
+            .await
+            .map(|target| (Protocol::HttpConnect, target))
+    }
+}
+
+/// reads the rest of a socks5 request, after the version
+async fn read_socks5<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> std::io::Result<Target> {
+    let invalid = |message: &str| Error::new(ErrorKind::InvalidData, format!("socks5: {message}"));
+
+    let mut methods = vec![0u8; stream.read_u8().await? as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&0x00) {
+        stream.write_all(&[0x05, 0xff]).await?;
+        return Err(invalid("somente conecções sem autenticação são aceitas"));
+    }
+    stream.write_all(&[0x05, 0x00]).await?;
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    if request[0] != 0x05 {
+        return Err(invalid("versão inválida"));
+    }
+    if request[1] != 0x01 {
+        // command not supported, only CONNECT is
+        stream
+            .write_all(&[0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+            .await?;
+        return Err(invalid("somente o comando CONNECT é aceito"));
+    }
+    let host = match request[3] {
+        0x01 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        0x04 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        0x03 => {
+            let mut name = vec![0u8; stream.read_u8().await? as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| invalid("nome inválido"))?
+        }
+        _ => return Err(invalid("tipo de endereço inválido")),
+    };
+    let port = stream.read_u16().await?;
+    Ok(Target { host, port })
+}
+
+/// reads the rest of an http request, after its first byte
+async fn read_http_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    first: u8,
+) -> std::io::Result<Target> {
+    // reads one byte at a time, so nothing after the request, that already belongs to the tunnel, is consumed
+    let mut request = vec![first];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() > 16 * 1024 {
+            return Err(Error::new(ErrorKind::InvalidData, "pedido muito grande"));
+        }
+        request.push(stream.read_u8().await?);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.lines().next().unwrap_or("").split(' ');
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if !method.eq_ignore_ascii_case("CONNECT") {
+        stream
+            .write_all(
+                b"HTTP/1.1 405 Method Not Allowed\r\nAllow: CONNECT\r\nContent-Length: 0\r\n\r\n",
+            )
+            .await?;
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("somente o método CONNECT é aceito, não {method:?}"),
+        ));
+    }
+    parse_target(target).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("destino inválido: {target:?}"),
+        )
+    })
+}
+
+/// `host:port` or `[ipv6]:port`, the port is required
+pub fn parse_target(text: &str) -> Option<Target> {
+    let (host, port) = text.trim().rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some(Target {
+        host: host.to_owned(),
+        port: port.parse().ok()?,
+    })
+}
+
+/// tells the program the connection to the destination is open
+pub async fn reply_success<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    protocol: Protocol,
+) -> std::io::Result<()> {
+    match protocol {
+        Protocol::Socks5 => {
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await
+        }
+        Protocol::HttpConnect => {
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+        }
+    }
+}
+
+/// tells the program the connection to the destination failed
+pub async fn reply_failure<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    protocol: Protocol,
+    failure: Failure,
+) -> std::io::Result<()> {
+    match protocol {
+        Protocol::Socks5 => {
+            let code = match failure {
+                Failure::NotAllowed => 0x02,
+                Failure::Unavailable => 0x01,
+            };
+            stream
+                .write_all(&[0x05, code, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await
+        }
+        Protocol::HttpConnect => {
+            let status = match failure {
+                Failure::NotAllowed => "403 Forbidden",
+                Failure::Unavailable => "502 Bad Gateway",
+            };
+            stream
+                .write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes())
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn target(host: &str, port: u16) -> Target {
+        Target {
+            host: host.to_owned(),
+            port,
+        }
+    }
+
+    /// runs `read_request` on the bytes sent by a program, returns its result and what was answered
+    async fn request(bytes: &[u8]) -> (std::io::Result<(Protocol, Target)>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        client.write_all(bytes).await.unwrap();
+        client.shutdown().await.unwrap();
+        let result = read_request(&mut server).await;
+        drop(server);
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        (result, reply)
+    }
+
+    #[test]
+    fn targets_are_parsed_as_they_are_written() {
+        for target in [
+            target("db.example.com", 5432),
+            target("10.0.0.1", 22),
+            target("::1", 443),
+        ] {
+            assert_eq!(parse_target(&target.to_string()), Some(target));
+        }
+        assert_eq!(parse_target(" [fd00::1]:22 "), Some(target("fd00::1", 22)));
+    }
+
+    #[test]
+    fn targets_without_a_host_or_a_port_are_refused() {
+        assert_eq!(parse_target(""), None);
+        assert_eq!(parse_target("db.example.com"), None);
+        assert_eq!(parse_target("db.example.com:"), None);
+        assert_eq!(parse_target(":22"), None);
+        assert_eq!(parse_target("[]:22"), None);
+    }
+
+    #[test]
+    fn ports_that_are_not_numbers_are_refused() {
+        assert_eq!(parse_target("db.example.com:ssh"), None);
+        assert_eq!(parse_target("db.example.com:70000"), None);
+        assert_eq!(parse_target("db.example.com:-1"), None);
+    }
+
+    #[tokio::test]
+    async fn socks5_asks_for_names_and_addresses() {
+        let mut bytes = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x03, 14];
+        bytes.extend_from_slice(b"db.example.com");
+        bytes.extend_from_slice(&5432u16.to_be_bytes());
+        let (result, reply) = request(&bytes).await;
+        assert_eq!(
+            result.unwrap(),
+            (Protocol::Socks5, target("db.example.com", 5432))
+        );
+        assert_eq!(reply, [0x05, 0x00]);
+
+        let bytes = [
+            0x05, 0x02, 0x02, 0x00, 0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0, 22,
+        ];
+        let (result, _) = request(&bytes).await;
+        assert_eq!(result.unwrap(), (Protocol::Socks5, target("10.0.0.1", 22)));
+
+        let mut bytes = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x04];
+        bytes.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        bytes.extend_from_slice(&443u16.to_be_bytes());
+        let (result, _) = request(&bytes).await;
+        assert_eq!(result.unwrap(), (Protocol::Socks5, target("::1", 443)));
+    }
+
+    #[tokio::test]
+    async fn a_socks5_request_cut_short_fails_at_its_end() {
+        let full = [0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0, 22];
+        for len in 1..full.len() {
+            let (result, _) = request(&full[..len]).await;
+            assert_eq!(
+                result.unwrap_err().kind(),
+                ErrorKind::UnexpectedEof,
+                "{len} bytes"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unsupported_socks5_requests_are_refused() {
+        // only username and password authentication offered
+        let (result, reply) = request(&[0x05, 0x01, 0x02]).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(reply, [0x05, 0xff]);
+        // BIND
+        let (result, reply) = request(&[0x05, 0x01, 0x00, 0x05, 0x02, 0x00, 0x01]).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(reply[2..4], [0x05, 0x07]);
+        // unknown address type
+        let (result, _) = request(&[0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x09]).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        // wrong version in the request
+        let (result, _) = request(&[0x05, 0x01, 0x00, 0x04, 0x01, 0x00, 0x01]).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        // name that is not utf-8
+        let (result, _) =
+            request(&[0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x03, 1, 0xff, 0, 22]).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn the_bytes_after_a_connect_belong_to_the_tunnel() {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        client
+            .write_all(b"CONNECT [::1]:22 HTTP/1.1\r\nHost: [::1]:22\r\n\r\nSSH-2.0")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        let result = read_request(&mut server).await;
+        assert_eq!(result.unwrap(), (Protocol::HttpConnect, target("::1", 22)));
+        // the bytes after the request belong to the tunnel
+        let mut rest = Vec::new();
+        server.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"SSH-2.0");
+    }
+
+    #[tokio::test]
+    async fn a_connect_cut_short_fails_at_its_end() {
+        let full = b"CONNECT db:5432 HTTP/1.1\r\nHost: db:5432\r\n\r\n";
+        for len in 1..full.len() {
+            let (result, _) = request(&full[..len]).await;
+            assert_eq!(
+                result.unwrap_err().kind(),
+                ErrorKind::UnexpectedEof,
+                "{len} bytes"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_other_than_connect_are_refused() {
+        let (result, reply) = request(b"GET / HTTP/1.1\r\nHost: db\r\n\r\n").await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(reply.starts_with(b"HTTP/1.1 405 "));
+        let (result, _) = request(b"CONNECT db HTTP/1.1\r\n\r\n").await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        let (result, _) = request(&[b'C'; 17 * 1024]).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
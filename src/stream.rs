@@ -0,0 +1,213 @@
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::addr::ListenAddr;
+
+/// the local side of a session, a tcp connection or a unix socket
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl Stream {
+    pub async fn readable(&self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(x) => x.readable().await,
+            #[cfg(unix)]
+            Stream::Unix(x) => x.readable().await,
+        }
+    }
+    pub async fn writable(&self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(x) => x.writable().await,
+            #[cfg(unix)]
+            Stream::Unix(x) => x.writable().await,
+        }
+    }
+    pub fn try_read(&self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(x) => x.try_read(buffer),
+            #[cfg(unix)]
+            Stream::Unix(x) => x.try_read(buffer),
+        }
+    }
+    pub fn try_write(&self, buffer: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(x) => x.try_write(buffer),
+            #[cfg(unix)]
+            Stream::Unix(x) => x.try_write(buffer),
+        }
+    }
+    /// closes the writing side, the other side reads the end of the stream and may still write
+    pub async fn shutdown_write(&mut self) -> std::io::Result<()> {
+        tokio::io::AsyncWriteExt::shutdown(self).await
+    }
+    /// makes the close send a reset, so the other side knows the session failed, unix sockets have no reset
+    pub fn set_zero_linger(&self) -> std::io::Result<()> {
+        match self {
+            // `set_zero_linger` only exists since tokio 1.50, that deprecated this
+            #[allow(deprecated)]
+            Stream::Tcp(x) => x.set_linger(Some(std::time::Duration::ZERO)),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+    /// the address of the other side, unix sockets have none
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Stream::Tcp(x) => x.peer_addr().ok(),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(x) => Pin::new(x).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(x) => Pin::new(x).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(x) => Pin::new(x).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(x) => Pin::new(x).poll_write(cx, buf),
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(x) => Pin::new(x).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(x) => Pin::new(x).poll_flush(cx),
+        }
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(x) => Pin::new(x).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(x) => Pin::new(x).poll_shutdown(cx),
+        }
+    }
+}
+
+/// a local listener, on tcp addresses or on a unix socket
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    pub async fn bind(listen: &ListenAddr) -> std::io::Result<Self> {
+        match listen {
+            ListenAddr::Tcp(addrs) => TcpListener::bind(&addrs[..]).await.map(Listener::Tcp),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                // a socket left behind by a previous run would make the bind fail
+                if std::fs::symlink_metadata(path)
+                    .is_ok_and(|x| std::os::unix::fs::FileTypeExt::is_socket(&x.file_type()))
+                {
+                    let _ = std::fs::remove_file(path);
+                }
+                tokio::net::UnixListener::bind(path).map(Listener::Unix)
+            }
+        }
+    }
+
+    /// accepts a connection, with the address of the other side for tcp
+    pub async fn accept(&self) -> std::io::Result<(Stream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(x) => x
+                .accept()
+                .await
+                .map(|(stream, peer)| (Stream::Tcp(stream), Some(peer))),
+            #[cfg(unix)]
+            Listener::Unix(x) => x
+                .accept()
+                .await
+                .map(|(stream, _)| (Stream::Unix(stream), None)),
+        }
+    }
+
+    /// where the listener is bound, as accepted by `addr::parse_listen_addr`
+    pub fn local_addr(&self) -> Option<String> {
+        match self {
+            Listener::Tcp(x) => x.local_addr().ok().map(|x| x.to_string()),
+            #[cfg(unix)]
+            Listener::Unix(x) => x
+                .local_addr()
+                .ok()
+                .and_then(|x| x.as_pathname().map(|x| format!("unix:{}", x.display()))),
+        }
+    }
+}
+
+/// a stream fed from stdin and copied to stdout, for a session run as `ssh` `ProxyCommand`
+/// the session needs the readiness of the stream, that stdin and stdout do not have, so they are bridged through a socket pair
+/// without unix sockets the pair is a loopback tcp connection
+/// the end of stdin shuts down the writing side of the stream, which the session sends as a half close
+/// the returned task ends once everything the session wrote was copied to stdout
+pub async fn stdio() -> std::io::Result<(Stream, tokio::task::JoinHandle<()>)> {
+    use tokio::io::AsyncWriteExt;
+
+    #[cfg(unix)]
+    let (local, remote) = {
+        let (local, remote) = tokio::net::UnixStream::pair()?;
+        (Stream::Unix(local), Stream::Unix(remote))
+    };
+    #[cfg(not(unix))]
+    let (local, remote) = {
+        let listener = TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).await?;
+        let local = TcpStream::connect(listener.local_addr()?).await?;
+        // any local program can connect to the port, only the connection made above is accepted
+        let remote = loop {
+            let (remote, peer) = listener.accept().await?;
+            if peer == local.local_addr()? {
+                break remote;
+            }
+            tracing::warn!("conecção de {peer} na ponte do stdio recusada");
+        };
+        (Stream::Tcp(local), Stream::Tcp(remote))
+    };
+    let (mut reader, mut writer) = tokio::io::split(remote);
+    tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await;
+        let _ = writer.shutdown().await;
+    });
+    let output = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        let _ = tokio::io::copy(&mut reader, &mut stdout).await;
+        let _ = stdout.flush().await;
+    });
+    Ok((local, output))
+}
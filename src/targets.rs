@@ -0,0 +1,358 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{access::Cidr, addr::Target};
+
+/// the host part of a `TargetPattern`
+#[derive(Debug, Clone)]
+enum HostPattern {
+    /// `*`, any host
+    Any,
+    /// `*.example.com`, the subdomains of a domain
+    Subdomains(String),
+    /// a single name, matched only by the name the client asked for
+    Name(String),
+    /// an ip or a network, matched by the addresses the name resolves to
+    Network(Cidr),
+}
+
+/// a destination clients may ask for, as in `db.example.com:5432`, `*.example.com:443`, `10.0.0.0/8:8000-8100` or `*:22`
+#[derive(Debug, Clone)]
+pub struct TargetPattern {
+    host: HostPattern,
+    /// first and last port allowed
+    ports: (u16, u16),
+}
+
+impl TargetPattern {
+    fn matches_port(&self, port: u16) -> bool {
+        self.ports.0 <= port && port <= self.ports.1
+    }
+    /// if the name asked for matches, without resolving it
+    fn matches_name(&self, host: &str) -> bool {
+        match &self.host {
+            HostPattern::Any => true,
+            HostPattern::Subdomains(domain) => {
+                host.len() > domain.len() + 1
+                    && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+                    && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+            }
+            HostPattern::Name(name) => name.eq_ignore_ascii_case(host),
+            HostPattern::Network(_) => false,
+        }
+    }
+}
+
+impl std::str::FromStr for TargetPattern {
+    type Err = ();
+    fn from_str(text: &str) -> Result<Self, ()> {
+        let (host, ports) = text.trim().rsplit_once(':').ok_or(())?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host = if host == "*" {
+            HostPattern::Any
+        } else if let Some(domain) = host.strip_prefix("*.") {
+            HostPattern::Subdomains(domain.to_owned())
+        } else if let Ok(cidr) = host.parse::<Cidr>() {
+            HostPattern::Network(cidr)
+        } else if !host.is_empty()
+            && host
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | '-' | '_'))
+        {
+            HostPattern::Name(host.to_owned())
+        } else {
+            return Err(());
+        };
+        let ports = match ports.split_once('-') {
+            _ if ports == "*" => (0, u16::MAX),
+            Some((first, last)) => (
+                first.trim().parse().map_err(|_| ())?,
+                last.trim().parse().map_err(|_| ())?,
+            ),
+            None => {
+                let port = ports.parse().map_err(|_| ())?;
+                (port, port)
+            }
+        };
+        if ports.0 > ports.1 {
+            return Err(());
+        }
+        Ok(Self { host, ports })
+    }
+}
+
+impl std::fmt::Display for TargetPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.host {
+            HostPattern::Any => f.write_str("*")?,
+            HostPattern::Subdomains(domain) => write!(f, "*.{domain}")?,
+            HostPattern::Name(name) => f.write_str(name)?,
+            HostPattern::Network(cidr) => write!(f, "[{cidr}]")?,
+        }
+        match self.ports {
+            (0, u16::MAX) => f.write_str(":*"),
+            (first, last) if first == last => write!(f, ":{first}"),
+            (first, last) => write!(f, ":{first}-{last}"),
+        }
+    }
+}
+
+/// parses a list of patterns separated by (;), (,) or spaces, invalid ones are logged and ignored
+pub fn parse_many_target_pattern(text: &str) -> Vec<TargetPattern> {
+    text.split([';', ',', ' '])
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .filter_map(|x| match x.parse() {
+            Ok(pattern) => Some(pattern),
+            Err(()) => {
+                tracing::warn!("destino inválido: {x:?}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// the destinations allowed to some clients
+/// there is no authentication, so the clients are told apart by their ip
+#[derive(Debug, Clone, Default)]
+pub struct TargetRule {
+    /// the networks of the clients the rule applies to, all clients if empty
+    pub clients: Vec<Cidr>,
+    pub allow: Vec<TargetPattern>,
+}
+
+/// which destinations clients may ask for in the `x-tow-target` header, instead of the tcp services of the server
+/// anything not allowed by a rule is refused
+#[derive(Debug, Clone, Default)]
+pub struct TargetPolicy {
+    pub rules: Vec<TargetRule>,
+    /// refuses loopback, link-local and cloud metadata addresses, unless allowed by an ip or network pattern
+    pub block_internal: bool,
+}
+
+/// why a destination asked by a client can not be connected to
+#[derive(Debug)]
+pub enum TargetError {
+    /// no rule allows the destination for the client, or every address it resolves to is blocked
+    Denied,
+    /// the name could not be resolved
+    Io(std::io::Error),
+}
+
+impl TargetPolicy {
+    fn patterns(&self, client: IpAddr, port: u16) -> impl Iterator<Item = &TargetPattern> {
+        self.rules
+            .iter()
+            .filter(move |x| x.clients.is_empty() || x.clients.iter().any(|x| x.contains(client)))
+            .flat_map(|x| &x.allow)
+            .filter(move |x| x.matches_port(port))
+    }
+
+    /// checks what can be known before resolving the name, so most refusals happen in the handshake
+    /// a destination allowed here may still be refused by `resolve`
+    pub fn may_allow(&self, client: IpAddr, target: &Target) -> bool {
+        let ip = target.host.parse::<IpAddr>().ok();
+        self.patterns(client, target.port)
+            .any(|pattern| match (&pattern.host, ip) {
+                (HostPattern::Network(cidr), Some(ip)) => cidr.contains(ip),
+                // a name may resolve into the network
+                (HostPattern::Network(_), None) => true,
+                _ => pattern.matches_name(&target.host),
+            })
+    }
+
+    /// resolves the destination, returning only the addresses the client is allowed to connect to
+    /// checking the resolved addresses, instead of the name, keeps names pointing to internal addresses out
+    pub async fn resolve(
+        &self,
+        client: IpAddr,
+        target: &Target,
+    ) -> Result<Vec<SocketAddr>, TargetError> {
+        let patterns = self.patterns(client, target.port).collect::<Vec<_>>();
+        if patterns.is_empty() {
+            return Err(TargetError::Denied);
+        }
+        let addrs = match target.host.parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, target.port)],
+            Err(_) => tokio::net::lookup_host((target.host.as_str(), target.port))
+                .await
+                .map_err(TargetError::Io)?
+                .collect(),
+        };
+        let allowed = addrs
+            .into_iter()
+            .filter(|addr| {
+                patterns.iter().any(|pattern| match &pattern.host {
+                    HostPattern::Network(cidr) => cidr.contains(addr.ip()),
+                    _ => {
+                        pattern.matches_name(&target.host)
+                            && !(self.block_internal && is_internal(addr.ip()))
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        if allowed.is_empty() {
+            return Err(TargetError::Denied);
+        }
+        Ok(allowed)
+    }
+}
+
+/// loopback, unspecified, link-local and the metadata services of the cloud providers
+fn is_internal(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    };
+    match ip {
+        IpAddr::V4(v4) => {
+            v4.is_loopback()
+                || v4.is_unspecified()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4 == Ipv4Addr::new(100, 100, 100, 200)
+        }
+        IpAddr::V6(v6) => {
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.segments()[0] & 0xffc0 == 0xfe80
+                || v6 == Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(text: &str) -> TargetPattern {
+        text.parse().unwrap()
+    }
+
+    fn target(host: &str, port: u16) -> Target {
+        Target {
+            host: host.to_owned(),
+            port,
+        }
+    }
+
+    fn rule(clients: &str, allow: &str) -> TargetRule {
+        TargetRule {
+            clients: crate::access::parse_many_cidr(clients),
+            allow: parse_many_target_pattern(allow),
+        }
+    }
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+
+    #[test]
+    fn patterns_are_written_as_they_are_parsed() {
+        for text in [
+            "*:22",
+            "*:*",
+            "*.example.com:443",
+            "db.internal:8000-8100",
+            "[10.0.0.0/8]:5432",
+            "[fd00::/8]:22",
+        ] {
+            assert_eq!(pattern(text).to_string(), text);
+        }
+        assert_eq!(pattern("10.0.0.0/8:5432").to_string(), "[10.0.0.0/8]:5432");
+        for text in [
+            "db.internal",
+            "db.internal:ssh",
+            "db.internal:8100-8000",
+            "bad host:22",
+            "10.0.0.0/33:22",
+        ] {
+            assert!(text.parse::<TargetPattern>().is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn subdomain_patterns_do_not_match_the_domain() {
+        let subdomains = pattern("*.example.com:443");
+        assert!(subdomains.matches_name("db.example.com"));
+        assert!(subdomains.matches_name("a.b.EXAMPLE.com"));
+        assert!(!subdomains.matches_name("example.com"));
+        assert!(!subdomains.matches_name("badexample.com"));
+        // networks are matched by the resolved addresses, never by the name
+        assert!(!pattern("10.0.0.0/8:22").matches_name("10.0.0.1"));
+    }
+
+    #[test]
+    fn anything_not_allowed_is_refused() {
+        let policy = TargetPolicy::default();
+        assert!(!policy.may_allow(CLIENT, &target("db.internal", 5432)));
+        let policy = TargetPolicy {
+            rules: vec![rule("", "db.internal:5432 *.example.com:8000-8100")],
+            block_internal: false,
+        };
+        assert!(policy.may_allow(CLIENT, &target("DB.internal", 5432)));
+        assert!(!policy.may_allow(CLIENT, &target("db.internal", 5433)));
+        assert!(policy.may_allow(CLIENT, &target("web.example.com", 8100)));
+        assert!(!policy.may_allow(CLIENT, &target("web.example.com", 8101)));
+        assert!(!policy.may_allow(CLIENT, &target("other.internal", 5432)));
+    }
+
+    #[test]
+    fn rules_apply_only_to_their_clients() {
+        let policy = TargetPolicy {
+            rules: vec![rule("192.168.1.0/24", "*:22"), rule("", "*:443")],
+            block_internal: false,
+        };
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(policy.may_allow(CLIENT, &target("host", 22)));
+        assert!(!policy.may_allow(other, &target("host", 22)));
+        assert!(policy.may_allow(other, &target("host", 443)));
+    }
+
+    #[tokio::test]
+    async fn internal_addresses_are_blocked_unless_allowed_by_network() {
+        let policy = TargetPolicy {
+            rules: vec![rule("", "*:80 [127.0.0.1/32]:8080")],
+            block_internal: true,
+        };
+        for host in ["127.0.0.1", "169.254.169.254", "::1", "::ffff:127.0.0.1"] {
+            assert!(
+                matches!(
+                    policy.resolve(CLIENT, &target(host, 80)).await,
+                    Err(TargetError::Denied)
+                ),
+                "{host}"
+            );
+        }
+        assert_eq!(
+            policy
+                .resolve(CLIENT, &target("10.0.0.1", 80))
+                .await
+                .unwrap(),
+            ["10.0.0.1:80".parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(
+            policy
+                .resolve(CLIENT, &target("127.0.0.1", 8080))
+                .await
+                .unwrap(),
+            ["127.0.0.1:8080".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn network_patterns_check_the_resolved_addresses() {
+        let policy = TargetPolicy {
+            rules: vec![rule("", "[10.0.0.0/8]:5432")],
+            block_internal: false,
+        };
+        // a name may resolve into the network, so it is only refused once resolved
+        assert!(policy.may_allow(CLIENT, &target("db.internal", 5432)));
+        assert!(!policy.may_allow(CLIENT, &target("192.168.0.1", 5432)));
+        assert!(matches!(
+            policy.resolve(CLIENT, &target("192.168.0.1", 5432)).await,
+            Err(TargetError::Denied)
+        ));
+        assert!(policy
+            .resolve(CLIENT, &target("10.1.2.3", 5432))
+            .await
+            .is_ok());
+    }
+}
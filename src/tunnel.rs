@@ -0,0 +1,73 @@
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use crate::shaping::{Shaper, Shaping};
+
+static TUNNELS: Mutex<Vec<Arc<TunnelStatus>>> = Mutex::new(Vec::new());
+
+/// the state of a client tunnel, a local listener forwarding to a websocket server
+pub struct TunnelStatus {
+    pub remote: String,
+    pub listen: Option<String>,
+    /// the limits of each session of the tunnel
+    pub session_shaping: Shaping,
+    /// the limits of all the sessions of the tunnel added, if any
+    pub(crate) shaper: Option<Arc<Shaper>>,
+    pub(crate) sessions: AtomicUsize,
+    pub(crate) bytes_tcp_to_ws: AtomicU64,
+    pub(crate) bytes_ws_to_tcp: AtomicU64,
+    /// websockets acquired again for sessions that lost theirs
+    pub(crate) reconnects: AtomicU64,
+    /// attempts to connect a websocket that failed
+    pub(crate) failed_connects: AtomicU64,
+}
+
+impl TunnelStatus {
+    pub fn tunnel_shaping(&self) -> Shaping {
+        self.shaper.as_ref().map(|x| x.shaping).unwrap_or_default()
+    }
+    pub fn sessions(&self) -> usize {
+        self.sessions.load(Ordering::Relaxed)
+    }
+    pub fn bytes_tcp_to_ws(&self) -> u64 {
+        self.bytes_tcp_to_ws.load(Ordering::Relaxed)
+    }
+    pub fn bytes_ws_to_tcp(&self) -> u64 {
+        self.bytes_ws_to_tcp.load(Ordering::Relaxed)
+    }
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+    pub fn failed_connects(&self) -> u64 {
+        self.failed_connects.load(Ordering::Relaxed)
+    }
+}
+
+/// adds a tunnel to the list returned by `tunnels`, it stays there until the end of the process
+pub(crate) fn register(
+    remote: String,
+    listen: Option<String>,
+    tunnel_shaping: Shaping,
+    session_shaping: Shaping,
+) -> Arc<TunnelStatus> {
+    let tunnel = Arc::new(TunnelStatus {
+        remote,
+        listen,
+        session_shaping,
+        shaper: Shaper::new_if_limited(tunnel_shaping).map(Arc::new),
+        sessions: AtomicUsize::new(0),
+        bytes_tcp_to_ws: AtomicU64::new(0),
+        bytes_ws_to_tcp: AtomicU64::new(0),
+        reconnects: AtomicU64::new(0),
+        failed_connects: AtomicU64::new(0),
+    });
+    TUNNELS.lock().unwrap().push(tunnel.clone());
+    tunnel
+}
+
+/// the tunnels running in this process
+pub fn tunnels() -> Vec<Arc<TunnelStatus>> {
+    TUNNELS.lock().unwrap().clone()
+}
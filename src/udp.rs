@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use async_tungstenite::{
+    tungstenite::{http, Message},
+    WebSocketStream,
+};
+use futures::{AsyncRead, AsyncWrite, StreamExt};
+use tokio::{net::UdpSocket, sync::mpsc};
+use tokio_util::bytes::Bytes;
+use tracing::Instrument;
+
+use crate::{close, metrics, proxy, shaping, tunnel::TunnelStatus, ClientOptions, SessionStatus};
+
+/// how long a flow lives without datagrams in either direction, if the idle timeout is not configured
+pub const DEFAULT_UDP_IDLE_TIMEOUT_MS: u64 = 60_000;
+/// the largest udp payload
+const MAX_DATAGRAM: usize = 65_535;
+/// datagrams waiting for the websocket of their flow, more are dropped as udp would
+const FLOW_QUEUE: usize = 64;
+
+/// the idle timeout of udp flows, from the idle timeout of the sessions if configured
+pub fn idle_timeout(idle: Option<Duration>) -> Duration {
+    idle.unwrap_or(Duration::from_millis(DEFAULT_UDP_IDLE_TIMEOUT_MS))
+}
+
+/// receives datagrams on the local socket, each source address gets its own websocket to the server
+/// there is no resuming: a flow whose websocket is lost ends, and the next datagram starts a new one
+pub async fn udp_to_ws_service(
+    connect_request: http::Request<()>,
+    socket: UdpSocket,
+    options: ClientOptions,
+) -> std::io::Result<std::convert::Infallible> {
+    let socket = Arc::new(socket);
+    let options = Arc::new(options);
+    let tunnel = crate::tunnel::register(
+        connect_request.uri().to_string(),
+        socket.local_addr().ok().map(|x| x.to_string()),
+        options.tunnel_shaping,
+        options.session_shaping,
+    );
+    let mut flows: HashMap<SocketAddr, mpsc::Sender<Bytes>> = HashMap::new();
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let (len, source) = match socket.recv_from(&mut buffer).await {
+            Ok(x) => x,
+            Err(error) => {
+                // on windows an icmp port unreachable from an earlier send shows up here
+                tracing::debug!("erro ao receber datagrama: {error:?}");
+                continue;
+            }
+        };
+        if !options.access.is_allowed(source.ip()) {
+            tracing::debug!("datagrama de {source} recusado pela lista de acesso");
+            continue;
+        }
+        let datagram = Bytes::copy_from_slice(&buffer[..len]);
+        if let Some(flow) = flows.get(&source).filter(|x| !x.is_closed()) {
+            let _ = flow.try_send(datagram);
+            continue;
+        }
+        flows.retain(|_, x| !x.is_closed());
+        let (sender, receiver) = mpsc::channel(FLOW_QUEUE);
+        let _ = sender.try_send(datagram);
+        flows.insert(source, sender);
+        let mut id = 0;
+        while id == 0 {
+            id = rand::random();
+        }
+        let span = tracing::info_span!("udp", %source, id = format!("{id:016x}"));
+        tokio::spawn(
+            handle_client_flow(
+                connect_request.clone(),
+                socket.clone(),
+                source,
+                receiver,
+                tunnel.clone(),
+                options.clone(),
+                id,
+            )
+            .instrument(span),
+        );
+    }
+}
+
+async fn handle_client_flow(
+    mut connect_request: http::Request<()>,
+    socket: Arc<UdpSocket>,
+    source: SocketAddr,
+    mut receiver: mpsc::Receiver<Bytes>,
+    tunnel: Arc<TunnelStatus>,
+    options: Arc<ClientOptions>,
+    id: u64,
+) {
+    tracing::info!("Novo fluxo udp");
+    let headers = connect_request.headers_mut();
+    headers.insert(
+        http::HeaderName::from_static("x-tow-id"),
+        http::HeaderValue::from_maybe_shared(id.to_string()).unwrap(),
+    );
+    headers.insert(
+        http::HeaderName::from_static("x-tow-protocol"),
+        http::HeaderValue::from_static("udp"),
+    );
+    let websocket = match proxy::connect(&options.proxy, connect_request.uri()).await {
+        Ok(stream) => {
+            async_tungstenite::tokio::client_async_tls_with_connector_and_config(
+                connect_request,
+                stream,
+                None,
+                None,
+            )
+            .await
+        }
+        Err(error) => Err(async_tungstenite::tungstenite::Error::Io(error)),
+    };
+    let mut ws = match websocket {
+        Ok((ws, _)) => ws,
+        Err(error) => {
+            tracing::warn!("erro na conecção do ws do fluxo udp: {error:?}");
+            return;
+        }
+    };
+    tunnel.sessions.fetch_add(1, Ordering::Relaxed);
+    let shapers = tunnel
+        .shaper
+        .clone()
+        .into_iter()
+        .chain(shaping::Shaper::new_if_limited(tunnel.session_shaping).map(Arc::new))
+        .collect::<Vec<_>>();
+    let idle = idle_timeout(options.session_timeouts.idle);
+    loop {
+        tokio::select! {
+            datagram = receiver.recv() => {
+                let Some(datagram) = datagram else { break };
+                tunnel.bytes_tcp_to_ws.fetch_add(datagram.len() as u64, Ordering::Relaxed);
+                shaping::wait(shaping::tcp_to_ws(&shapers, datagram.len() as u64)).await;
+                if let Err(error) = ws.send(Message::Binary(datagram)).await {
+                    tracing::warn!("erro ao enviar datagrama pelo ws: {error:?}");
+                    break;
+                }
+            }
+            message = ws.next() => match message {
+                Some(Ok(Message::Binary(datagram))) => {
+                    tunnel.bytes_ws_to_tcp.fetch_add(datagram.len() as u64, Ordering::Relaxed);
+                    shaping::wait(shaping::ws_to_tcp(&shapers, datagram.len() as u64)).await;
+                    let _ = socket.send_to(&datagram, source).await;
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(error)) => {
+                    tracing::warn!("erro no ws do fluxo udp: {error:?}");
+                    break;
+                }
+            },
+            () = tokio::time::sleep(idle) => {
+                let _ = ws.close(None).await;
+                break;
+            }
+        }
+    }
+    tunnel.sessions.fetch_sub(1, Ordering::Relaxed);
+    tracing::info!("Fluxo udp encerrado");
+}
+
+/// relays the datagrams of one flow between the websocket and a udp socket connected to the target
+/// until the websocket closes or no datagram passes for `idle`, returns why the flow ended, for the audit log
+pub(crate) async fn relay<S: AsyncRead + AsyncWrite + Unpin>(
+    mut ws: WebSocketStream<S>,
+    socket: UdpSocket,
+    idle: Duration,
+    shapers: &[Arc<shaping::Shaper>],
+    status: &SessionStatus,
+) -> &'static str {
+    metrics::add(&metrics::UDP_FLOWS, 1);
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+    let reason = loop {
+        tokio::select! {
+            received = socket.recv(&mut buffer) => match received {
+                Ok(len) => {
+                    metrics::add(&metrics::BYTES_TCP_TO_WS, len as u64);
+                    status.bytes_tcp_to_ws.fetch_add(len as u64, Ordering::Relaxed);
+                    shaping::wait(shaping::tcp_to_ws(shapers, len as u64)).await;
+                    let datagram = Bytes::copy_from_slice(&buffer[..len]);
+                    if let Err(error) = ws.send(Message::Binary(datagram)).await {
+                        tracing::warn!("erro ao enviar datagrama pelo ws: {error:?}");
+                        break "ws-error";
+                    }
+                }
+                // an icmp port unreachable from an earlier send, the target may come up later
+                Err(error) => tracing::debug!("erro ao receber datagrama: {error:?}"),
+            },
+            message = ws.next() => match message {
+                Some(Ok(Message::Binary(datagram))) => {
+                    metrics::add(&metrics::BYTES_WS_TO_TCP, datagram.len() as u64);
+                    status.bytes_ws_to_tcp.fetch_add(datagram.len() as u64, Ordering::Relaxed);
+                    shaping::wait(shaping::ws_to_tcp(shapers, datagram.len() as u64)).await;
+                    let _ = socket.send(&datagram).await;
+                }
+                Some(Ok(Message::Close(_))) | None => break "closed",
+                Some(Ok(_)) => {}
+                Some(Err(error)) => {
+                    tracing::warn!("erro no ws do fluxo udp: {error:?}");
+                    break "ws-error";
+                }
+            },
+            () = tokio::time::sleep(idle) => {
+                let _ = ws.close(None).await;
+                break close::CloseReason::IdleTimeout.as_str();
+            }
+        }
+    };
+    metrics::sub(&metrics::UDP_FLOWS, 1);
+    tracing::info!("Fluxo udp encerrado");
+    reason
+}
+
+/// a udp socket connected to the first of the addresses, bound to any local address of the same family
+pub async fn connect(addrs: &[SocketAddr]) -> std::io::Result<UdpSocket> {
+    let addr = addrs
+        .first()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "nenhum endereço udp"))?;
+    let local: SocketAddr = if addr.is_ipv4() {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// a flow relayed to a udp socket, returns the websocket of the client, the socket of the target and the relay
+    async fn flow(
+        idle: Duration,
+    ) -> (
+        WebSocketStream<async_tungstenite::tokio::ConnectStream>,
+        UdpSocket,
+        tokio::task::JoinHandle<&'static str>,
+    ) {
+        let target = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = connect(&[target.local_addr().unwrap()]).await.unwrap();
+        target.connect(socket.local_addr().unwrap()).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let ws = async_tungstenite::tokio::accept_async(stream)
+                .await
+                .unwrap();
+            let status = SessionStatus::new(crate::Direction::WsToTcp);
+            relay(ws, socket, idle, &[], &status).await
+        });
+        let (ws, _) = async_tungstenite::tokio::connect_async(url).await.unwrap();
+        (ws, target, relay)
+    }
+
+    /// the next datagram the target receives
+    async fn recv(target: &UdpSocket) -> Vec<u8> {
+        let mut buffer = vec![0u8; MAX_DATAGRAM];
+        let len = tokio::time::timeout(Duration::from_secs(5), target.recv(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        buffer.truncate(len);
+        buffer
+    }
+
+    #[tokio::test]
+    async fn each_datagram_is_one_message() {
+        let (mut ws, target, _) = flow(Duration::from_secs(60)).await;
+        target.send(b"a").await.unwrap();
+        target.send(b"bcd").await.unwrap();
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            Message::binary(&b"a"[..])
+        );
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            Message::binary(&b"bcd"[..])
+        );
+        ws.send(Message::binary(&b"xyz"[..])).await.unwrap();
+        ws.send(Message::binary(Vec::new())).await.unwrap();
+        let large = vec![7u8; 60_000];
+        ws.send(Message::binary(large.clone())).await.unwrap();
+        assert_eq!(recv(&target).await, b"xyz");
+        assert_eq!(recv(&target).await, b"");
+        assert_eq!(recv(&target).await, large);
+    }
+
+    #[tokio::test]
+    async fn messages_too_large_for_a_datagram_are_dropped() {
+        let (mut ws, target, _) = flow(Duration::from_secs(60)).await;
+        ws.send(Message::binary(vec![0u8; MAX_DATAGRAM + 1]))
+            .await
+            .unwrap();
+        ws.send(Message::text("not a datagram")).await.unwrap();
+        ws.send(Message::binary(&b"after"[..])).await.unwrap();
+        assert_eq!(recv(&target).await, b"after");
+    }
+
+    #[tokio::test]
+    async fn flows_expire_when_no_datagram_passes() {
+        let (mut ws, target, relay) = flow(Duration::from_millis(300)).await;
+        // each datagram postpones the expiry
+        for index in 0..5u8 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            ws.send(Message::binary(vec![index])).await.unwrap();
+            assert_eq!(recv(&target).await, [index]);
+        }
+        let close = tokio::time::timeout(Duration::from_secs(5), ws.next())
+            .await
+            .unwrap();
+        assert!(matches!(close, Some(Ok(Message::Close(_))) | None));
+        assert_eq!(relay.await.unwrap(), "idle-timeout");
+    }
+
+    #[test]
+    fn the_idle_timeout_of_the_sessions_applies_to_flows() {
+        assert_eq!(
+            idle_timeout(None),
+            Duration::from_millis(DEFAULT_UDP_IDLE_TIMEOUT_MS)
+        );
+        assert_eq!(
+            idle_timeout(Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+    }
+}